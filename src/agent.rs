@@ -46,5 +46,6 @@ impl Agent {
     pub async fn userauth(&self, username: &str, identity: &PublicKey) -> Result<(), Error> {
         let aio = self.aio.clone();
         into_the_future!(aio; &mut || { self.inner.userauth(username, identity) })
+            .map_err(Error::auth_failed("publickey"))
     }
 }
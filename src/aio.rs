@@ -1,12 +1,32 @@
 use crate::{BlockDirections, Error};
+use libssh2_sys as raw;
 use mio::{net::TcpStream, Ready};
 use ssh2::Session;
-use std::{io, task::Context};
-use tokio::io::PollEvented;
+use std::{
+    collections::VecDeque,
+    future, io, mem,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{io::PollEvented, runtime::Handle, time};
 
 pub struct Aio {
     poll_evented: PollEvented<TcpStream>,
     session: Session,
+    poisoned: AtomicBool,
+    deferred: Mutex<VecDeque<Deferred>>,
+}
+
+/// An sftp handle that was dropped while still open. `ssh2` would close it
+/// with a blocking round trip, so it is closed before the next request on
+/// the session instead.
+pub enum Deferred {
+    File(ssh2::File),
+    Sftp(ssh2::Sftp),
 }
 
 impl Aio {
@@ -14,9 +34,69 @@ impl Aio {
         Ok(Self {
             poll_evented: PollEvented::new(TcpStream::from_stream(stream)?)?,
             session,
+            poisoned: AtomicBool::new(false),
+            deferred: Mutex::new(VecDeque::new()),
         })
     }
 
+    /// Queues `handle` to be closed by [`close_deferred`](Aio::close_deferred).
+    pub fn defer_close(&self, handle: Deferred) {
+        self.deferred.lock().unwrap().push_back(handle);
+    }
+
+    /// Closes the queued handles, oldest first, so that a close libssh2 has
+    /// started is always finished before the next one. Returns `false` if
+    /// libssh2 would block; call it again once the socket is ready.
+    pub fn close_deferred(&self) -> bool {
+        let mut deferred = self.deferred.lock().unwrap();
+        while let Some(handle) = deferred.front_mut() {
+            let res = match handle {
+                Deferred::File(file) => file.close(),
+                Deferred::Sftp(sftp) => sftp.shutdown(),
+            };
+            match res {
+                Err(e) if e.code() == raw::LIBSSH2_ERROR_EAGAIN => return false,
+                Ok(()) => drop(deferred.pop_front()),
+                // libssh2 frees the handle whatever the error, so it cannot
+                // be closed again; ssh2 would try to on drop. Callers only
+                // get here between requests, so this is a failure of the
+                // connection or the server, not an interleaved close.
+                Err(_) => mem::forget(deferred.pop_front()),
+            }
+        }
+        true
+    }
+
+    /// Records that a libssh2 call was abandoned after it had started, which
+    /// leaves libssh2 halfway through a request.
+    pub fn poison(&self) {
+        self.poisoned.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the socket still looks connected: the peer has not
+    /// closed it and no error is pending. Peeking consumes nothing, so
+    /// libssh2 still sees any data that has arrived.
+    pub fn is_connected(&self) -> bool {
+        match self.poll_evented.get_ref().peek(&mut [0]) {
+            Ok(0) => false,
+            Ok(_) => true,
+            Err(e) => e.kind() == io::ErrorKind::WouldBlock,
+        }
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        match self.session.timeout() {
+            0 => None,
+            ms => Some(Duration::from_millis(u64::from(ms))),
+        }
+    }
+
+    /// Registers `ctx` for readiness in the direction libssh2 is blocked on,
+    /// as reported by [`block_directions`](ssh2::Session::block_directions).
     pub fn set_waker(&self, ctx: &mut Context<'_>) -> io::Result<()> {
         match self.session.block_directions() {
             BlockDirections::Both => {
@@ -29,8 +109,70 @@ impl Aio {
             BlockDirections::Outbound => {
                 self.poll_evented.clear_write_ready(ctx)?;
             }
-            BlockDirections::None => {}
+            BlockDirections::None => {
+                // libssh2 reported EAGAIN without waiting on the socket, so
+                // there is no readiness event to wait for; poll again.
+                ctx.waker().wake_by_ref();
+            }
         }
         Ok(())
     }
 }
+
+impl Drop for Aio {
+    /// Nothing else can use the session once the last handle to it is gone,
+    /// so the handles still queued are closed on a task of their own. They
+    /// are leaked if that is not possible.
+    fn drop(&mut self) {
+        let deferred = mem::take(self.deferred.get_mut().unwrap());
+        if deferred.is_empty() {
+            return;
+        }
+        let stream = self.poll_evented.get_ref().try_clone();
+        match (Handle::try_current(), stream) {
+            (Ok(handle), Ok(stream)) if !self.is_poisoned() => {
+                let session = self.session.clone();
+                handle.spawn(async move {
+                    let poll_evented = match PollEvented::new(stream) {
+                        Ok(poll_evented) => poll_evented,
+                        Err(_) => return deferred.into_iter().for_each(mem::forget),
+                    };
+                    let aio = Aio {
+                        poll_evented,
+                        session,
+                        // Makes the drop of `aio` leak whatever is left if
+                        // the task fails or is cancelled.
+                        poisoned: AtomicBool::new(true),
+                        deferred: Mutex::new(deferred),
+                    };
+                    let close = future::poll_fn(|cx| {
+                        if aio.close_deferred() {
+                            Poll::Ready(Ok(()))
+                        } else {
+                            aio.set_waker(cx).map(|()| Poll::Pending)?
+                        }
+                    });
+                    let _: io::Result<()> = match aio.timeout() {
+                        Some(timeout) => time::timeout(timeout, close)
+                            .await
+                            .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into())),
+                        None => close.await,
+                    };
+                });
+            }
+            _ => deferred.into_iter().for_each(mem::forget),
+        }
+    }
+}
+
+/// Calls [`Aio::set_waker`], or fails if the session has no tcp stream to
+/// wait on. Without one nothing would ever wake the task up again.
+pub fn set_waker(aio: &Option<Aio>, ctx: &mut Context<'_>) -> io::Result<()> {
+    match aio {
+        Some(aio) => aio.set_waker(ctx),
+        None => Err(io::Error::new(
+            io::ErrorKind::NotConnected,
+            "the session would block but has no tcp stream; call set_tcp_stream first",
+        )),
+    }
+}
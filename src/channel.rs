@@ -1,20 +1,51 @@
-use crate::{aio::Aio, into_the_future, Error};
-use ssh2::{self, ExitSignal, ExtendedData, PtyModes, ReadWindow, Stream, WriteWindow};
+use crate::{
+    aio::{self, Aio},
+    into_the_future,
+    util::{RateLimit, ReadTimeout},
+    Error,
+};
+use bytes::Buf;
+use ssh2::{self, ExitSignal, ExtendedData, PtyModes, ReadWindow, WriteWindow};
 use std::{
+    cmp,
     convert::From,
     future::Future,
     io,
-    io::{Read, Write},
+    io::{IoSlice, Read, Write},
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
+    time::Duration,
 };
 use tokio::io::{AsyncRead, AsyncWrite};
 
+/// Most data libssh2 sends in one channel packet, and so in one write.
+pub(crate) const MAX_WRITE_SIZE: usize = 32 * 1024;
+
 /// See [`Channel`](ssh2::Channel).
+///
+/// Dropping a channel frees it without waiting for the server, so it does
+/// not block, but the remote end is not told the channel is gone. Call
+/// [`close`](Channel::close) and [`wait_close`](Channel::wait_close) first to
+/// shut it down cleanly.
+///
+/// Shutting down the `AsyncWrite` side flushes and sends EOF, like
+/// [`send_eof`](Channel::send_eof); the channel stays open so the remaining
+/// output can still be read.
 pub struct Channel {
     inner: ssh2::Channel,
     aio: Arc<Option<Aio>>,
+    read_timeout: ReadTimeout,
+    rate_limit: RateLimit,
+    eof_sent: bool,
+    transferred: u64,
+}
+
+/// See [`Stream`](ssh2::Stream).
+pub struct Stream {
+    inner: ssh2::Stream,
+    aio: Arc<Option<Aio>>,
+    read_timeout: ReadTimeout,
 }
 
 impl Channel {
@@ -22,10 +53,75 @@ impl Channel {
         Self {
             inner: channel,
             aio,
+            read_timeout: ReadTimeout::default(),
+            rate_limit: RateLimit::default(),
+            eof_sent: false,
+            transferred: 0,
         }
     }
 
+    /// Makes reads through `AsyncRead` fail with [`io::ErrorKind::TimedOut`]
+    /// once no data has arrived for `timeout`, for example because the server
+    /// has gone away. `None`, the default, waits forever.
+    ///
+    /// Streams returned by [`stream`](Channel::stream) and
+    /// [`stderr`](Channel::stderr) start out with the timeout set here.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout.set(timeout)
+    }
+
+    /// Returns the timeout set with [`set_read_timeout`](Channel::set_read_timeout).
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout.get()
+    }
+
+    /// Limits reads and writes through `AsyncRead` and `AsyncWrite` to
+    /// `bytes_per_sec` bytes per second, counting both directions together;
+    /// `None`, the default, does not limit them. Up to a second's worth of
+    /// bytes may go through in a burst after the channel has been idle.
+    ///
+    /// Streams returned by [`stream`](Channel::stream) and
+    /// [`stderr`](Channel::stderr) are not limited. Reading slower only
+    /// slows the server down once the channel's window is full; see
+    /// [`adjust_receive_window`](Channel::adjust_receive_window).
+    pub fn set_rate_limit(&mut self, bytes_per_sec: Option<u64>) {
+        self.rate_limit.set(bytes_per_sec)
+    }
+
+    /// Returns the limit set with [`set_rate_limit`](Channel::set_rate_limit).
+    pub fn rate_limit(&self) -> Option<u64> {
+        self.rate_limit.get()
+    }
+
+    /// Returns the wrapped [`ssh2::Channel`]. Available with the
+    /// `raw-access` feature; see [`Session::raw`](crate::Session::raw) for
+    /// the rules.
+    #[cfg(feature = "raw-access")]
+    pub fn raw(&self) -> &ssh2::Channel {
+        &self.inner
+    }
+
+    /// Like [`raw`](Channel::raw), but mutable, as most of
+    /// [`ssh2::Channel`]'s methods need.
+    #[cfg(feature = "raw-access")]
+    pub fn raw_mut(&mut self) -> &mut ssh2::Channel {
+        &mut self.inner
+    }
+
+    /// Returns the number of bytes read from and written to the channel
+    /// through `AsyncRead` and `AsyncWrite` so far. Data read through
+    /// [`stream`](Channel::stream) or [`stderr`](Channel::stderr) is not
+    /// included.
+    pub fn bytes_transferred(&self) -> u64 {
+        self.transferred
+    }
+
     /// See [`setenv`](ssh2::Channel::setenv).
+    ///
+    /// Servers commonly refuse variables not listed in their `AcceptEnv`
+    /// setting. Such a refusal is reported as [`Error::SSH2`] with code
+    /// `LIBSSH2_ERROR_CHANNEL_REQUEST_DENIED` (-22) and leaves the channel
+    /// usable; any other error means the request did not get through.
     pub async fn setenv(&mut self, var: &str, val: &str) -> Result<(), Error> {
         let aio = self.aio.clone();
         into_the_future!(aio; &mut || { self.inner.setenv(var, val) })
@@ -82,14 +178,46 @@ impl Channel {
         into_the_future!(aio; &mut || { self.inner.process_startup(request, message) })
     }
 
+    /// Delivers a signal such as `"TERM"` or `"KILL"` (without the `SIG`
+    /// prefix) to the remote process.
+    ///
+    /// This sends a `signal` channel request through
+    /// [`process_startup`](Channel::process_startup), since this version of
+    /// libssh2 has no dedicated API for it. Servers that do not support
+    /// signals (OpenSSH before 7.9, for example) reject the request.
+    pub async fn send_signal(&mut self, signal: &str) -> Result<(), Error> {
+        self.process_startup("signal", Some(signal)).await
+    }
+
+    /// Asks the server to forward connections to the local SSH agent, like
+    /// `ssh -A`.
+    ///
+    /// This must be called before [`exec`](Channel::exec),
+    /// [`shell`](Channel::shell) or [`subsystem`](Channel::subsystem). It
+    /// sends an `auth-agent-req@openssh.com` channel request through
+    /// [`process_startup`](Channel::process_startup), since this version of
+    /// libssh2 has no dedicated API for it; servers that disallow agent
+    /// forwarding reject the request. Note that libssh2 1.9 silently drops
+    /// the `auth-agent@openssh.com` channels the server opens back for each
+    /// agent connection, so remote clients see the agent socket but get no
+    /// answer from it yet.
+    pub async fn request_auth_agent_forwarding(&mut self) -> Result<(), Error> {
+        self.process_startup("auth-agent-req@openssh.com", None)
+            .await
+    }
+
     /// See [`stderr`](ssh2::Channel::stderr).
-    pub fn stderr(&mut self) -> Stream {
-        self.inner.stderr()
+    pub fn stderr(&self) -> Stream {
+        Stream::new(self.inner.stderr(), self.aio.clone(), self.read_timeout())
     }
 
     /// See [`stream`](ssh2::Channel::stream).
-    pub fn stream(&mut self, stream_id: i32) -> Stream {
-        self.inner.stream(stream_id)
+    pub fn stream(&self, stream_id: i32) -> Stream {
+        Stream::new(
+            self.inner.stream(stream_id),
+            self.aio.clone(),
+            self.read_timeout(),
+        )
     }
 
     /// See [`handle_extended_data`](ssh2::Channel::handle_extended_data).
@@ -99,11 +227,36 @@ impl Channel {
     }
 
     /// See [`exit_status`](ssh2::Channel::exit_status).
+    ///
+    /// A process killed by a signal has no exit status, so this returns 0
+    /// for it; check [`exit_signal`](Channel::exit_signal) to tell the two
+    /// apart. The status is only known once the server has closed the
+    /// channel, which may come after reads have returned EOF; use
+    /// [`wait_exit_status`](Channel::wait_exit_status) to wait for it.
     pub fn exit_status(&self) -> Result<i32, Error> {
         self.inner.exit_status().map_err(From::from)
     }
 
+    /// Waits for the server to close the channel and returns the exit
+    /// status of the remote process.
+    ///
+    /// Read the output to EOF first: any that is still unread is buffered
+    /// until the receive window is full, after which the remote process
+    /// stalls and this never returns. Once the server has closed the
+    /// channel, this does not touch the connection, so it also succeeds if
+    /// the server hung up right after.
+    pub async fn wait_exit_status(&mut self) -> Result<i32, Error> {
+        self.wait_eof().await?;
+        self.wait_close().await?;
+        self.exit_status()
+    }
+
     /// See [`exit_signal`](ssh2::Channel::exit_signal).
+    ///
+    /// The signal name is reported without the `SIG` prefix, such as
+    /// `"KILL"`, and is `None` unless the remote process was terminated by a
+    /// signal. Like [`exit_status`](Channel::exit_status), it is only known
+    /// once the channel has been closed by the server.
     pub fn exit_signal(&self) -> Result<ExitSignal, Error> {
         self.inner.exit_signal().map_err(From::from)
     }
@@ -132,7 +285,9 @@ impl Channel {
     /// See [`send_eof`](ssh2::Channel::send_eof).
     pub async fn send_eof(&mut self) -> Result<(), Error> {
         let aio = self.aio.clone();
-        into_the_future!(aio; &mut || { self.inner.send_eof() })
+        into_the_future!(aio; &mut || { self.inner.send_eof() })?;
+        self.eof_sent = true;
+        Ok(())
     }
 
     /// See [`wait_eof`](ssh2::Channel::wait_eof).
@@ -160,17 +315,33 @@ impl AsyncRead for Channel {
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<io::Result<usize>> {
-        loop {
-            let res = self.inner.read(buf);
-            match res {
-                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    if let Some(ref aio) = *self.aio {
-                        aio.set_waker(cx)?;
-                    }
-                    return Poll::Pending;
-                }
-                Err(e) => return Poll::Ready(Err(e)),
-                Ok(val) => return Poll::Ready(Ok(val)),
+        // libssh2 answers an empty read with EAGAIN until the remote sends
+        // EOF, and nothing would wake the task up for it.
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let len = match self.rate_limit.poll_acquire(cx, buf.len()) {
+            Poll::Ready(len) => len,
+            Poll::Pending => return Poll::Pending,
+        };
+        let res = self.inner.read(&mut buf[..len]);
+        match res {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.read_timeout.poll_blocked(cx)?;
+                aio::set_waker(&self.aio, cx)?;
+                Poll::Pending
+            }
+            // The server may hang up right after closing the channel, and
+            // libssh2 then reports the failed transport read even though the
+            // EOF and exit status are already in; with nothing left to read
+            // that is simply the end of the output.
+            Err(_) if self.inner.eof() => Poll::Ready(Ok(0)),
+            Err(e) => Poll::Ready(Err(e)),
+            Ok(val) => {
+                self.read_timeout.reset();
+                self.rate_limit.consume(val);
+                self.transferred += val as u64;
+                Poll::Ready(Ok(val))
             }
         }
     }
@@ -182,97 +353,190 @@ impl AsyncWrite for Channel {
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<Result<usize, io::Error>> {
-        loop {
-            let res = self.inner.write(buf);
-            match res {
-                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    if let Some(ref aio) = *self.aio {
-                        aio.set_waker(cx)?;
-                    }
-                    return Poll::Pending;
+        let len = match self.rate_limit.poll_acquire(cx, buf.len()) {
+            Poll::Ready(len) => len,
+            Poll::Pending => return Poll::Pending,
+        };
+        let res = self.inner.write(&buf[..len]);
+        match res {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.rate_limit.blocked(len);
+                aio::set_waker(&self.aio, cx)?;
+                Poll::Pending
+            }
+            Err(e) => {
+                self.rate_limit.consume(0);
+                Poll::Ready(Err(e))
+            }
+            Ok(val) => {
+                self.rate_limit.consume(val);
+                self.transferred += val as u64;
+                Poll::Ready(Ok(val))
+            }
+        }
+    }
+
+    fn poll_write_buf<B: Buf>(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut B,
+    ) -> Poll<Result<usize, io::Error>> {
+        if !buf.has_remaining() {
+            return Poll::Ready(Ok(0));
+        }
+        let mut slices = [IoSlice::new(&[]); 64];
+        let count = buf.bytes_vectored(&mut slices);
+        // Each write is sent as its own packet, so gather small slices into
+        // one, as `File` does. An unchanged `buf` gathers the same bytes
+        // again, which keeps retries after `WouldBlock` valid for libssh2.
+        let res = if count > 1 && slices[0].len() < MAX_WRITE_SIZE {
+            let mut data = Vec::with_capacity(MAX_WRITE_SIZE);
+            for slice in &slices[..count] {
+                let len = cmp::min(slice.len(), MAX_WRITE_SIZE - data.len());
+                data.extend_from_slice(&slice[..len]);
+                if data.len() == MAX_WRITE_SIZE {
+                    break;
                 }
-                Err(e) => return Poll::Ready(Err(e)),
-                Ok(val) => return Poll::Ready(Ok(val)),
             }
+            self.poll_write(cx, &data)
+        } else {
+            self.poll_write(cx, buf.bytes())
+        };
+        match res {
+            Poll::Ready(Ok(n)) => {
+                buf.advance(n);
+                Poll::Ready(Ok(n))
+            }
+            res => res,
         }
     }
 
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
-        loop {
-            let res = self.inner.flush();
-            match res {
-                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    if let Some(ref aio) = *self.aio {
-                        aio.set_waker(cx)?;
-                    }
-                    return Poll::Pending;
-                }
-                Err(e) => return Poll::Ready(Err(e)),
-                Ok(val) => return Poll::Ready(Ok(val)),
+        let res = self.inner.flush();
+        match res {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                aio::set_waker(&self.aio, cx)?;
+                Poll::Pending
             }
+            Err(e) => Poll::Ready(Err(e)),
+            Ok(val) => Poll::Ready(Ok(val)),
         }
     }
 
-    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
-        Poll::Ready(Ok(().into()))
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        if self.as_mut().poll_flush(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        if self.eof_sent {
+            return Poll::Ready(Ok(()));
+        }
+        match self.inner.send_eof().map_err(io::Error::from) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                aio::set_waker(&self.aio, cx)?;
+                Poll::Pending
+            }
+            Err(e) => Poll::Ready(Err(e)),
+            Ok(()) => {
+                self.eof_sent = true;
+                Poll::Ready(Ok(()))
+            }
+        }
     }
 }
 
-/*
-impl<'channel> Read for Stream<'channel> {
-    fn read(&mut self, data: &mut [u8]) -> io::Result<usize> {
-        if self.channel.eof() {
-            return Ok(0);
-        }
+impl Stream {
+    pub(crate) fn new(
+        stream: ssh2::Stream,
+        aio: Arc<Option<Aio>>,
+        read_timeout: Option<Duration>,
+    ) -> Self {
+        let mut stream = Self {
+            inner: stream,
+            aio,
+            read_timeout: ReadTimeout::default(),
+        };
+        stream.set_read_timeout(read_timeout);
+        stream
+    }
+
+    /// See [`Channel::set_read_timeout`].
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout.set(timeout)
+    }
+
+    /// Returns the timeout set with [`set_read_timeout`](Stream::set_read_timeout).
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout.get()
+    }
+}
 
-        let data = match self.channel.read_limit {
-            Some(amt) => {
-                let len = data.len();
-                &mut data[..cmp::min(amt as usize, len)]
+impl AsyncRead for Stream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        // See `Channel::poll_read`.
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let res = self.inner.read(buf);
+        match res {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.read_timeout.poll_blocked(cx)?;
+                aio::set_waker(&self.aio, cx)?;
+                Poll::Pending
             }
-            None => data,
-        };
-        let ret = unsafe {
-            let rc = raw::libssh2_channel_read_ex(
-                self.channel.raw,
-                self.id as c_int,
-                data.as_mut_ptr() as *mut _,
-                data.len() as size_t,
-            );
-            self.channel.sess.rc(rc as c_int).map(|()| rc as usize)
-        };
-        match ret {
-            Ok(n) => {
-                if let Some(ref mut amt) = self.channel.read_limit {
-                    *amt -= n as u64;
-                }
-                Ok(n)
+            // `ssh2::Stream` has no `eof`, but its `read` checks the channel
+            // for EOF before going to the transport, so a second attempt
+            // tells a hangup after the end of the output from a real error.
+            Err(e) => match self.inner.read(buf) {
+                Ok(0) => Poll::Ready(Ok(0)),
+                _ => Poll::Ready(Err(e)),
+            },
+            Ok(val) => {
+                self.read_timeout.reset();
+                Poll::Ready(Ok(val))
             }
-            Err(e) => Err(e.into()),
         }
     }
 }
 
-impl<'channel> Write for Stream<'channel> {
-    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
-        unsafe {
-            let rc = raw::libssh2_channel_write_ex(
-                self.channel.raw,
-                self.id as c_int,
-                data.as_ptr() as *mut _,
-                data.len() as size_t,
-            );
-            self.channel.sess.rc(rc as c_int).map(|()| rc as usize)
+impl AsyncWrite for Stream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let res = self.inner.write(buf);
+        match res {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                aio::set_waker(&self.aio, cx)?;
+                Poll::Pending
+            }
+            Err(e) => Poll::Ready(Err(e)),
+            Ok(val) => Poll::Ready(Ok(val)),
         }
-        .map_err(Into::into)
     }
 
-    fn flush(&mut self) -> io::Result<()> {
-        unsafe {
-            let rc = raw::libssh2_channel_flush_ex(self.channel.raw, self.id as c_int);
-            self.channel.sess.rc(rc)
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let res = self.inner.flush();
+        match res {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                aio::set_waker(&self.aio, cx)?;
+                Poll::Pending
+            }
+            Err(e) => Poll::Ready(Err(e)),
+            Ok(val) => Poll::Ready(Ok(val)),
         }
-        .map_err(Into::into)
+    }
+
+    /// Flushes the stream. EOF applies to the whole channel, so it is only
+    /// sent by shutting down the [`Channel`] itself.
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        self.poll_flush(cx)
     }
 }
-*/
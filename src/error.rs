@@ -1,5 +1,10 @@
-use ssh2;
-use std::{convert::From, error, fmt, io};
+use std::{
+    convert::From,
+    error, fmt, io,
+    path::{Path, PathBuf},
+    string::FromUtf8Error,
+    time::Duration,
+};
 
 /// Representation of an error.
 #[derive(Debug)]
@@ -8,6 +13,92 @@ pub enum Error {
     SSH2(ssh2::Error),
     // An io error.
     Io(io::Error),
+    // An error that occurred while operating on the given path.
+    Path(PathBuf, Box<Error>),
+    // The server rejected authentication with the given method.
+    AuthFailed(&'static str, ssh2::Error),
+    // The operation did not complete within the session timeout.
+    Timeout(Duration),
+    // An earlier operation on the session was cancelled partway through, so
+    // libssh2 is left in an unknown state.
+    Poisoned,
+    // The server's host key does not have the expected fingerprint.
+    HostKeyMismatch { expected: String, actual: String },
+    // Data that should have been text was not valid UTF-8.
+    Utf8(FromUtf8Error),
+}
+
+/// Error code libssh2 returns when the server rejects the credentials.
+pub(crate) const LIBSSH2_ERROR_AUTHENTICATION_FAILED: i32 = -18;
+/// Error code libssh2 returns when the server rejects a public key signature.
+const LIBSSH2_ERROR_PUBLICKEY_UNVERIFIED: i32 = -19;
+/// Error codes libssh2 returns when the connection itself failed.
+const LIBSSH2_ERROR_BANNER_RECV: i32 = -2;
+const LIBSSH2_ERROR_SOCKET_SEND: i32 = -7;
+const LIBSSH2_ERROR_TIMEOUT: i32 = -9;
+const LIBSSH2_ERROR_SOCKET_DISCONNECT: i32 = -13;
+const LIBSSH2_ERROR_SOCKET_TIMEOUT: i32 = -30;
+const LIBSSH2_ERROR_SOCKET_RECV: i32 = -43;
+
+impl Error {
+    pub(crate) fn with_path<E: Into<Error>>(path: &Path) -> impl FnOnce(E) -> Error + '_ {
+        move |e| Error::Path(path.to_owned(), Box::new(e.into()))
+    }
+
+    pub(crate) fn auth_failed(method: &'static str) -> impl FnOnce(Error) -> Error {
+        move |e| match e {
+            Error::SSH2(e)
+                if e.code() == LIBSSH2_ERROR_AUTHENTICATION_FAILED
+                    || e.code() == LIBSSH2_ERROR_PUBLICKEY_UNVERIFIED =>
+            {
+                Error::AuthFailed(method, e)
+            }
+            e => e,
+        }
+    }
+
+    /// Returns `true` if the error may go away when the operation is tried
+    /// again, as [`retry`](crate::retry) does.
+    ///
+    /// Transient errors are timeouts and failures of the connection: resets,
+    /// broken pipes, unexpected disconnects. Everything the server answered
+    /// deliberately is permanent, including every sftp failure such as a
+    /// missing file or a denied permission, and so are failed
+    /// authentication, a mismatched host key and a
+    /// [poisoned](crate::Session::is_poisoned) session.
+    ///
+    /// A transient error usually leaves its session unusable, so trying
+    /// again means connecting again.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Timeout(_) => true,
+            Error::Path(_, e) => e.is_transient(),
+            Error::Io(e) => matches!(
+                e.kind(),
+                io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::NotConnected
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::Interrupted
+                    | io::ErrorKind::UnexpectedEof
+            ),
+            Error::SSH2(e) => matches!(
+                e.code(),
+                LIBSSH2_ERROR_BANNER_RECV
+                    | LIBSSH2_ERROR_SOCKET_SEND
+                    | LIBSSH2_ERROR_TIMEOUT
+                    | LIBSSH2_ERROR_SOCKET_DISCONNECT
+                    | LIBSSH2_ERROR_SOCKET_TIMEOUT
+                    | LIBSSH2_ERROR_SOCKET_RECV
+            ),
+            Error::AuthFailed(..)
+            | Error::Poisoned
+            | Error::HostKeyMismatch { .. }
+            | Error::Utf8(_) => false,
+        }
+    }
 }
 
 impl fmt::Display for Error {
@@ -15,15 +106,32 @@ impl fmt::Display for Error {
         match self {
             Error::Io(e) => e.fmt(f),
             Error::SSH2(e) => e.fmt(f),
+            Error::Path(path, e) => write!(f, "{}: {}", path.display(), e),
+            Error::AuthFailed(method, e) => write!(f, "{} authentication failed: {}", method, e),
+            Error::Timeout(timeout) => write!(f, "operation timed out after {:?}", timeout),
+            Error::Poisoned => write!(
+                f,
+                "session is unusable after an operation was cancelled partway through"
+            ),
+            Error::HostKeyMismatch { expected, actual } => write!(
+                f,
+                "host key fingerprint {} does not match the expected {}",
+                actual, expected
+            ),
+            Error::Utf8(e) => write!(f, "invalid UTF-8: {}", e),
         }
     }
 }
 
 impl error::Error for Error {
-    fn description(&self) -> &str {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
-            Error::SSH2(e) => e.message(),
-            Error::Io(e) => e.description(),
+            Error::SSH2(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::Path(_, e) => Some(e),
+            Error::AuthFailed(_, e) => Some(e),
+            Error::Utf8(e) => Some(e),
+            Error::Timeout(_) | Error::Poisoned | Error::HostKeyMismatch { .. } => None,
         }
     }
 }
@@ -39,3 +147,21 @@ impl From<io::Error> for Error {
         Error::Io(e)
     }
 }
+
+impl From<FromUtf8Error> for Error {
+    fn from(e: FromUtf8Error) -> Error {
+        Error::Utf8(e)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        match e {
+            Error::Io(e) => e,
+            Error::SSH2(e) => e.into(),
+            Error::Timeout(_) => io::Error::new(io::ErrorKind::TimedOut, e),
+            Error::Utf8(_) => io::Error::new(io::ErrorKind::InvalidData, e),
+            e => io::Error::other(e),
+        }
+    }
+}
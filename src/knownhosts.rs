@@ -0,0 +1,84 @@
+use crate::Error;
+use ssh2::{self, CheckResult, Host, KnownHostFileKind, KnownHostKeyFormat};
+use std::{convert::From, path::Path};
+
+/// See [`KnownHosts`](ssh2::KnownHosts).
+///
+/// Unlike the `ssh2` type, [`check`](KnownHosts::check) and
+/// [`check_port`](KnownHosts::check_port) compare against the host key of the
+/// session the collection was created from, so they are meant to be called
+/// after [`handshake`](crate::Session::handshake) and before authenticating.
+/// None of these methods touch the network.
+pub struct KnownHosts {
+    inner: ssh2::KnownHosts,
+    session: ssh2::Session,
+}
+
+impl KnownHosts {
+    pub(crate) fn new(known_hosts: ssh2::KnownHosts, session: ssh2::Session) -> Self {
+        Self {
+            inner: known_hosts,
+            session,
+        }
+    }
+
+    /// See [`read_file`](ssh2::KnownHosts::read_file).
+    pub fn read_file(&mut self, file: &Path, kind: KnownHostFileKind) -> Result<u32, Error> {
+        self.inner.read_file(file, kind).map_err(From::from)
+    }
+
+    /// See [`read_str`](ssh2::KnownHosts::read_str).
+    pub fn read_str(&mut self, s: &str, kind: KnownHostFileKind) -> Result<(), Error> {
+        self.inner.read_str(s, kind).map_err(From::from)
+    }
+
+    /// See [`write_file`](ssh2::KnownHosts::write_file).
+    pub fn write_file(&self, file: &Path, kind: KnownHostFileKind) -> Result<(), Error> {
+        self.inner.write_file(file, kind).map_err(From::from)
+    }
+
+    /// See [`write_string`](ssh2::KnownHosts::write_string).
+    pub fn write_string(&self, host: &Host, kind: KnownHostFileKind) -> Result<String, Error> {
+        self.inner.write_string(host, kind).map_err(From::from)
+    }
+
+    /// See [`hosts`](ssh2::KnownHosts::hosts).
+    pub fn hosts(&self) -> Result<Vec<Host>, Error> {
+        self.inner.hosts().map_err(From::from)
+    }
+
+    /// See [`remove`](ssh2::KnownHosts::remove).
+    pub fn remove(&self, host: &Host) -> Result<(), Error> {
+        self.inner.remove(host).map_err(From::from)
+    }
+
+    /// Checks the session's host key for `host` against the collection.
+    ///
+    /// Returns [`CheckResult::Failure`] if the session has no host key yet.
+    /// See [`check`](ssh2::KnownHosts::check).
+    pub fn check(&self, host: &str) -> CheckResult {
+        match self.session.host_key() {
+            Some((key, _)) => self.inner.check(host, key),
+            None => CheckResult::Failure,
+        }
+    }
+
+    /// Same as [`check`](KnownHosts::check), but takes a port as well.
+    pub fn check_port(&self, host: &str, port: u16) -> CheckResult {
+        match self.session.host_key() {
+            Some((key, _)) => self.inner.check_port(host, port, key),
+            None => CheckResult::Failure,
+        }
+    }
+
+    /// See [`add`](ssh2::KnownHosts::add).
+    pub fn add(
+        &mut self,
+        host: &str,
+        key: &[u8],
+        comment: &str,
+        fmt: KnownHostKeyFormat,
+    ) -> Result<(), Error> {
+        self.inner.add(host, key, comment, fmt).map_err(From::from)
+    }
+}
@@ -2,19 +2,34 @@ mod agent;
 mod aio;
 mod channel;
 mod error;
+mod knownhosts;
 mod listener;
+mod pool;
+mod progress;
+mod raw_sftp;
+mod retry;
 mod session;
 mod sftp;
+#[cfg(feature = "testing")]
+pub mod testing;
 mod util;
 
 pub use agent::Agent;
-pub use channel::Channel;
+pub use channel::{Channel, Stream};
 pub use error::Error;
+pub use knownhosts::KnownHosts;
 pub use listener::Listener;
-pub use session::Session;
-pub use sftp::Sftp;
+pub use pool::{PooledSession, SessionPool};
+pub use progress::{Progress, ProgressReceiver};
+pub use retry::{retry, RetryPolicy};
+pub use session::{Session, TraceFlags};
+pub use sftp::{
+    BufferedReader, BufferedWriter, CopyMethod, File, Metadata, ReadDir, Sftp, SortBy, StatVfs,
+};
+pub use util::timeout;
 
 pub use ssh2::{
-    BlockDirections, ExitSignal, FileStat, FileType, Host, KnownHostFileKind, KnownHosts,
-    OpenFlags, Prompt, PtyModes, PublicKey, ReadWindow, RenameFlags, ScpFileStat, WriteWindow,
+    BlockDirections, CheckResult, DisconnectCode, ExitSignal, FileStat, FileType, HashType, Host,
+    HostKeyType, KnownHostFileKind, KnownHostKeyFormat, MethodType, OpenFlags, Prompt,
+    PtyModeOpcode, PtyModes, PublicKey, ReadWindow, RenameFlags, ScpFileStat, WriteWindow,
 };
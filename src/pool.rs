@@ -0,0 +1,211 @@
+use crate::{Error, Session};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    future::{self, Future},
+    ops::Deref,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Poll, Waker},
+};
+
+type Connect =
+    dyn Fn() -> Pin<Box<dyn Future<Output = Result<Session, Error>> + Send>> + Send + Sync;
+
+/// A pool of up to `size` authenticated sessions to the same server.
+///
+/// Sessions are opened on demand by the `connect` function given to
+/// [`new`](SessionPool::new) and handed out one task at a time through
+/// [`get`](SessionPool::get), since a session cannot run several libssh2
+/// calls at once. Open channels and sftp handles from the returned
+/// [`PooledSession`] and drop them before the session itself, which then
+/// goes back to the pool.
+///
+/// Before an idle session is handed out again, it is checked with
+/// [`is_alive`](Session::is_alive); sessions that fail the check, such as
+/// ones the server has closed or that were
+/// [poisoned](Session::is_poisoned), are replaced by new ones. The check
+/// only sends a keepalive if `connect` enabled them with
+/// [`set_keepalive`](Session::set_keepalive) and the interval has passed.
+///
+/// The pool is cheap to clone and can be shared between tasks.
+#[derive(Clone)]
+pub struct SessionPool {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    connect: Box<Connect>,
+    size: usize,
+    state: Mutex<State>,
+}
+
+struct State {
+    idle: Vec<Session>,
+    // Sessions that are idle, handed out or being connected.
+    open: usize,
+    // The latest waker of each task waiting in `get`, oldest first.
+    waiters: BTreeMap<u64, Waker>,
+    next_waiter: u64,
+}
+
+/// A task's place in the queue of [`State::waiters`], given up on drop.
+struct Waiter<'a> {
+    pool: &'a Inner,
+    id: Option<u64>,
+}
+
+/// A session checked out of a [`SessionPool`], returned to it on drop.
+pub struct PooledSession {
+    session: Option<Session>,
+    pool: Arc<Inner>,
+}
+
+enum Slot {
+    Idle(Session),
+    New,
+}
+
+impl SessionPool {
+    /// Creates a pool of at most `size` sessions, each opened and
+    /// authenticated by `connect`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is zero.
+    pub fn new<F, Fut>(size: usize, connect: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Session, Error>> + Send + 'static,
+    {
+        assert!(
+            size > 0,
+            "a session pool needs room for at least one session"
+        );
+        Self {
+            inner: Arc::new(Inner {
+                connect: Box::new(move || Box::pin(connect())),
+                size,
+                state: Mutex::new(State {
+                    idle: Vec::new(),
+                    open: 0,
+                    waiters: BTreeMap::new(),
+                    next_waiter: 0,
+                }),
+            }),
+        }
+    }
+
+    /// Returns the maximum number of sessions.
+    pub fn size(&self) -> usize {
+        self.inner.size
+    }
+
+    /// Checks out a session, waiting for one to be returned if all `size`
+    /// sessions are in use.
+    ///
+    /// Errors from `connect` are returned as is and free the slot again.
+    pub async fn get(&self) -> Result<PooledSession, Error> {
+        let mut waiter = Waiter {
+            pool: &self.inner,
+            id: None,
+        };
+        let slot = future::poll_fn(|cx| {
+            let mut state = self.inner.state.lock().unwrap();
+            let slot = if let Some(session) = state.idle.pop() {
+                Slot::Idle(session)
+            } else if state.open < self.inner.size {
+                state.open += 1;
+                Slot::New
+            } else {
+                let state = &mut *state;
+                let id = *waiter.id.get_or_insert_with(|| {
+                    state.next_waiter += 1;
+                    state.next_waiter
+                });
+                state.waiters.insert(id, cx.waker().clone());
+                return Poll::Pending;
+            };
+            if let Some(id) = waiter.id.take() {
+                state.waiters.remove(&id);
+            }
+            Poll::Ready(slot)
+        })
+        .await;
+
+        // From here on the slot is released if this future is dropped.
+        let mut pooled = PooledSession {
+            session: None,
+            pool: self.inner.clone(),
+        };
+        if let Slot::Idle(session) = slot {
+            if session.is_alive().await {
+                pooled.session = Some(session);
+                return Ok(pooled);
+            }
+        }
+        pooled.session = Some((self.inner.connect)().await?);
+        Ok(pooled)
+    }
+}
+
+impl fmt::Debug for SessionPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.inner.state.lock().unwrap();
+        f.debug_struct("SessionPool")
+            .field("size", &self.inner.size)
+            .field("open", &state.open)
+            .field("idle", &state.idle.len())
+            .field("waiters", &state.waiters.len())
+            .finish()
+    }
+}
+
+impl PooledSession {
+    /// Closes the session instead of returning it to the pool, for example
+    /// after an error that leaves it in doubt.
+    pub fn discard(mut self) {
+        self.session = None;
+    }
+}
+
+impl Deref for PooledSession {
+    type Target = Session;
+
+    fn deref(&self) -> &Session {
+        self.session.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledSession {
+    fn drop(&mut self) {
+        let mut state = self.pool.state.lock().unwrap();
+        match self.session.take() {
+            Some(session) if !session.is_poisoned() => state.idle.push(session),
+            _ => state.open -= 1,
+        }
+        state.wake_next();
+    }
+}
+
+impl State {
+    /// Wakes the longest waiting task to take the slot just freed.
+    fn wake_next(&mut self) {
+        if let Some((_, waker)) = self.waiters.pop_first() {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            let mut state = self.pool.state.lock().unwrap();
+            // Without an entry the task was woken for a slot that it now
+            // leaves unclaimed, so pass the wakeup on.
+            if state.waiters.remove(&id).is_none() {
+                state.wake_next();
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    collections::VecDeque,
+    future,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+use tokio::stream::Stream;
+
+/// A progress update for one file of a transfer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Progress {
+    /// The file being transferred.
+    pub path: PathBuf,
+    /// Bytes of it transferred so far.
+    pub done: u64,
+    /// Its total size in bytes.
+    pub total: u64,
+}
+
+/// The receiving end of the progress updates of a transfer, such as the one
+/// started by [`Sftp::upload_dir_progress`](crate::Sftp::upload_dir_progress).
+///
+/// The receiver is a [`Stream`] of updates that ends once the transfer has
+/// finished or been dropped; [`next`](ProgressReceiver::next) reads one
+/// without a `StreamExt` import. Updates for the same file that have not
+/// been read yet are merged, so a slow reader sees fewer, larger steps
+/// rather than falling behind.
+#[derive(Debug)]
+pub struct ProgressReceiver {
+    shared: Arc<Mutex<Shared>>,
+}
+
+pub(crate) struct ProgressSender {
+    shared: Arc<Mutex<Shared>>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    queue: VecDeque<Progress>,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+pub(crate) fn channel() -> (ProgressSender, ProgressReceiver) {
+    let shared = Arc::new(Mutex::new(Shared {
+        queue: VecDeque::new(),
+        closed: false,
+        waker: None,
+    }));
+    (
+        ProgressSender {
+            shared: shared.clone(),
+        },
+        ProgressReceiver { shared },
+    )
+}
+
+impl ProgressReceiver {
+    /// Waits for the next update, or returns `None` once the transfer is
+    /// over and every update has been read.
+    pub async fn next(&mut self) -> Option<Progress> {
+        future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl Stream for ProgressReceiver {
+    type Item = Progress;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Progress>> {
+        let mut shared = self.shared.lock().unwrap();
+        if let Some(progress) = shared.queue.pop_front() {
+            Poll::Ready(Some(progress))
+        } else if shared.closed {
+            Poll::Ready(None)
+        } else {
+            shared.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl ProgressSender {
+    pub fn send(&self, path: &Path, done: u64, total: u64) {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.queue.back_mut() {
+            Some(last) if last.path == path => {
+                last.done = done;
+                last.total = total;
+            }
+            _ => shared.queue.push_back(Progress {
+                path: path.to_owned(),
+                done,
+                total,
+            }),
+        }
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for ProgressSender {
+    fn drop(&mut self) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.closed = true;
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+}
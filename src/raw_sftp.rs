@@ -0,0 +1,271 @@
+//! Just enough of the sftp protocol to send the requests libssh2 cannot,
+//! such as OpenSSH's extensions, on a channel of their own. See
+//! draft-ietf-secsh-filexfer-02 and OpenSSH's PROTOCOL file.
+
+use crate::{aio::Aio, into_the_future, Channel, Error};
+use std::{
+    future::Future,
+    io,
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const SSH_FXP_INIT: u8 = 1;
+const SSH_FXP_VERSION: u8 = 2;
+const SSH_FXP_OPEN: u8 = 3;
+const SSH_FXP_CLOSE: u8 = 4;
+const SSH_FXP_STATUS: u8 = 101;
+const SSH_FXP_HANDLE: u8 = 102;
+pub(crate) const SSH_FXP_EXTENDED: u8 = 200;
+const SSH_FXP_EXTENDED_REPLY: u8 = 201;
+
+pub(crate) const SSH_FXF_READ: u32 = 0x01;
+pub(crate) const SSH_FXF_WRITE: u32 = 0x02;
+pub(crate) const SSH_FXF_CREAT: u32 = 0x08;
+pub(crate) const SSH_FXF_TRUNC: u32 = 0x10;
+const SSH_FILEXFER_ATTR_PERMISSIONS: u32 = 0x04;
+
+const SSH_FX_OK: u32 = 0;
+const SSH_FX_NO_SUCH_FILE: u32 = 2;
+const SSH_FX_PERMISSION_DENIED: u32 = 3;
+const SSH_FX_OP_UNSUPPORTED: u32 = 8;
+
+/// Largest reply accepted, well above what the replies read here need, so a
+/// broken server cannot make us allocate without bound.
+const MAX_PACKET: usize = 256 * 1024;
+
+/// An sftp session on its own channel, next to the one libssh2 runs.
+pub(crate) struct RawSftp {
+    channel: Channel,
+    extensions: Vec<(Vec<u8>, Vec<u8>)>,
+    next_id: u32,
+}
+
+impl RawSftp {
+    /// Opens a session channel, starts the sftp subsystem on it and
+    /// negotiates version 3.
+    pub(crate) async fn open(
+        session: &ssh2::Session,
+        aio: Arc<Option<Aio>>,
+    ) -> Result<RawSftp, Error> {
+        let channel = {
+            let aio = aio.clone();
+            into_the_future!(aio; &mut || { session.channel_session() })?
+        };
+        let mut channel = Channel::new(channel, aio);
+        channel.subsystem("sftp").await?;
+        let mut init = Vec::new();
+        put_u32(&mut init, 3);
+        write_packet(&mut channel, SSH_FXP_INIT, &init).await?;
+        let (kind, version) = read_packet(&mut channel).await?;
+        if kind != SSH_FXP_VERSION {
+            return Err(protocol_error("expected SSH_FXP_VERSION"));
+        }
+        let mut version = &version[..];
+        get_u32(&mut version)?;
+        let mut extensions = Vec::new();
+        while !version.is_empty() {
+            let name = get_string(&mut version)?.to_vec();
+            let data = get_string(&mut version)?.to_vec();
+            extensions.push((name, data));
+        }
+        Ok(RawSftp {
+            channel,
+            extensions,
+            next_id: 0,
+        })
+    }
+
+    /// Returns whether the server advertised version `data` of the
+    /// extension `name`.
+    pub(crate) fn supports(&self, name: &[u8], data: &[u8]) -> bool {
+        self.extensions
+            .iter()
+            .any(|(n, d)| n.as_slice() == name && d.as_slice() == data)
+    }
+
+    /// Sends a request of type `kind`, with `body` following the request
+    /// ID, and returns the type and body of the reply.
+    async fn request(&mut self, kind: u8, body: &[u8]) -> Result<(u8, Vec<u8>), Error> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let mut payload = Vec::with_capacity(4 + body.len());
+        put_u32(&mut payload, id);
+        payload.extend_from_slice(body);
+        write_packet(&mut self.channel, kind, &payload).await?;
+        let (kind, mut reply) = read_packet(&mut self.channel).await?;
+        if reply.len() < 4 || reply[..4] != id.to_be_bytes() {
+            return Err(protocol_error("reply to an unknown request"));
+        }
+        Ok((kind, reply.split_off(4)))
+    }
+
+    /// Sends a request that the server answers with a status, and turns a
+    /// failure status into an error.
+    pub(crate) async fn status_request(&mut self, kind: u8, body: &[u8]) -> Result<(), Error> {
+        let (kind, status) = self.request(kind, body).await?;
+        if kind != SSH_FXP_STATUS {
+            return Err(protocol_error("expected SSH_FXP_STATUS"));
+        }
+        check_status(&status)
+    }
+
+    /// Sends an extended request that the server answers with an
+    /// `SSH_FXP_EXTENDED_REPLY`, and returns the body of the reply.
+    pub(crate) async fn extended_request(&mut self, body: &[u8]) -> Result<Vec<u8>, Error> {
+        let (kind, reply) = self.request(SSH_FXP_EXTENDED, body).await?;
+        match kind {
+            SSH_FXP_EXTENDED_REPLY => Ok(reply),
+            SSH_FXP_STATUS => {
+                check_status(&reply)?;
+                Err(protocol_error("expected SSH_FXP_EXTENDED_REPLY"))
+            }
+            _ => Err(protocol_error("expected SSH_FXP_EXTENDED_REPLY")),
+        }
+    }
+
+    /// Opens `path` with the `SSH_FXF_*` flags `pflags`, creating it with
+    /// `perm` if given, and returns its handle.
+    pub(crate) async fn open_handle(
+        &mut self,
+        path: &Path,
+        pflags: u32,
+        perm: Option<u32>,
+    ) -> Result<Vec<u8>, Error> {
+        let mut body = Vec::new();
+        put_string(&mut body, &path_bytes(path)?);
+        put_u32(&mut body, pflags);
+        match perm {
+            Some(perm) => {
+                put_u32(&mut body, SSH_FILEXFER_ATTR_PERMISSIONS);
+                put_u32(&mut body, perm);
+            }
+            None => put_u32(&mut body, 0),
+        }
+        let (kind, reply) = self.request(SSH_FXP_OPEN, &body).await?;
+        match kind {
+            SSH_FXP_HANDLE => Ok(get_string(&mut &reply[..])?.to_vec()),
+            SSH_FXP_STATUS => {
+                check_status(&reply)?;
+                Err(protocol_error("expected SSH_FXP_HANDLE"))
+            }
+            _ => Err(protocol_error("expected SSH_FXP_HANDLE")),
+        }
+    }
+
+    /// Closes a handle from [`open_handle`](RawSftp::open_handle).
+    pub(crate) async fn close_handle(&mut self, handle: &[u8]) -> Result<(), Error> {
+        let mut body = Vec::new();
+        put_string(&mut body, handle);
+        self.status_request(SSH_FXP_CLOSE, &body).await
+    }
+
+    /// Closes the channel, which also closes any handles left open.
+    pub(crate) async fn close(mut self) -> Result<(), Error> {
+        self.channel.close().await
+    }
+}
+
+fn check_status(mut status: &[u8]) -> Result<(), Error> {
+    let code = get_u32(&mut status)?;
+    let message = String::from_utf8_lossy(get_string(&mut status)?).into_owned();
+    let kind = match code {
+        SSH_FX_OK => return Ok(()),
+        SSH_FX_NO_SUCH_FILE => io::ErrorKind::NotFound,
+        SSH_FX_PERMISSION_DENIED => io::ErrorKind::PermissionDenied,
+        SSH_FX_OP_UNSUPPORTED => io::ErrorKind::Unsupported,
+        _ => io::ErrorKind::Other,
+    };
+    Err(io::Error::new(kind, message).into())
+}
+
+fn protocol_error(msg: &str) -> Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("sftp: {}", msg)).into()
+}
+
+pub(crate) fn put_u32(buf: &mut Vec<u8>, n: u32) {
+    buf.extend_from_slice(&n.to_be_bytes());
+}
+
+pub(crate) fn put_u64(buf: &mut Vec<u8>, n: u64) {
+    buf.extend_from_slice(&n.to_be_bytes());
+}
+
+pub(crate) fn put_string(buf: &mut Vec<u8>, s: &[u8]) {
+    put_u32(buf, s.len() as u32);
+    buf.extend_from_slice(s);
+}
+
+pub(crate) fn get_u64(buf: &mut &[u8]) -> Result<u64, Error> {
+    if buf.len() < 8 {
+        return Err(protocol_error("truncated packet"));
+    }
+    let (n, rest) = buf.split_at(8);
+    *buf = rest;
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(n);
+    Ok(u64::from_be_bytes(bytes))
+}
+
+fn get_u32(buf: &mut &[u8]) -> Result<u32, Error> {
+    if buf.len() < 4 {
+        return Err(protocol_error("truncated packet"));
+    }
+    let (n, rest) = buf.split_at(4);
+    *buf = rest;
+    Ok(u32::from_be_bytes([n[0], n[1], n[2], n[3]]))
+}
+
+fn get_string<'a>(buf: &mut &'a [u8]) -> Result<&'a [u8], Error> {
+    let len = get_u32(buf)? as usize;
+    if buf.len() < len {
+        return Err(protocol_error("truncated packet"));
+    }
+    let (s, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(s)
+}
+
+async fn write_packet(channel: &mut Channel, kind: u8, payload: &[u8]) -> Result<(), Error> {
+    let mut packet = Vec::with_capacity(5 + payload.len());
+    put_u32(&mut packet, payload.len() as u32 + 1);
+    packet.push(kind);
+    packet.extend_from_slice(payload);
+    channel.write_all(&packet).await?;
+    Ok(())
+}
+
+async fn read_packet(channel: &mut Channel) -> Result<(u8, Vec<u8>), Error> {
+    let mut len = [0; 4];
+    channel.read_exact(&mut len).await?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len == 0 || len > MAX_PACKET {
+        return Err(protocol_error("bad packet length"));
+    }
+    let mut packet = vec![0; len];
+    channel.read_exact(&mut packet).await?;
+    let payload = packet.split_off(1);
+    Ok((packet[0], payload))
+}
+
+/// Encodes `path` the way libssh2 does: as is on Unix, and as UTF-8 with
+/// forward slashes elsewhere.
+#[cfg(unix)]
+pub(crate) fn path_bytes(path: &Path) -> Result<Vec<u8>, Error> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(path.as_os_str().as_bytes().to_vec())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn path_bytes(path: &Path) -> Result<Vec<u8>, Error> {
+    let path = path.to_str().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "only unicode paths may be used on this platform",
+        )
+    })?;
+    Ok(path.replace('\\', "/").into_bytes())
+}
@@ -0,0 +1,109 @@
+use crate::Error;
+use std::{cmp, future::Future, time::Duration};
+use tokio::time;
+
+/// How often and how patiently [`retry`] reissues a failed operation.
+///
+/// The wait before each new attempt starts at the initial backoff and
+/// doubles every time, up to the maximum. The default makes three attempts,
+/// waiting 100 ms and then 200 ms, with the backoff capped at 10 s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Creates a policy that makes at most `max_attempts` attempts, counting
+    /// the first, with the default backoff.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_attempts` is zero.
+    pub fn new(max_attempts: u32) -> Self {
+        assert!(
+            max_attempts > 0,
+            "a retry policy needs at least one attempt"
+        );
+        Self {
+            max_attempts,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+
+    /// Waits `initial` before the second attempt, doubling the wait for
+    /// every later one but never waiting longer than `max`.
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Returns the maximum number of attempts.
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Returns how long to wait after the given failed attempt, counting
+    /// from one.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt - 1).unwrap_or(u32::MAX);
+        cmp::min(
+            self.initial_backoff
+                .checked_mul(factor)
+                .unwrap_or(self.max_backoff),
+            self.max_backoff,
+        )
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(3)
+    }
+}
+
+/// Runs the future returned by `op` until it succeeds, fails with an error
+/// that is not [transient](Error::is_transient), or `policy` runs out of
+/// attempts; the last error is returned.
+///
+/// `op` is called again for every attempt. Since a transient error usually
+/// leaves the session it happened on unusable, `op` should connect anew or
+/// take its session from a [`SessionPool`](crate::SessionPool), which
+/// replaces sessions that are gone. Operations that are not idempotent,
+/// such as appending to a file, may have taken effect before the
+/// connection failed.
+///
+/// ```no_run
+/// # async fn run(pool: async_ssh2::SessionPool) -> Result<(), async_ssh2::Error> {
+/// use async_ssh2::{retry, RetryPolicy};
+/// use std::path::Path;
+///
+/// let policy = RetryPolicy::new(5);
+/// let stat = retry(&policy, || async {
+///     let sess = pool.get().await?;
+///     let sftp = sess.sftp().await?;
+///     sftp.stat(Path::new("data.bin")).await
+/// })
+/// .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn retry<T, F, Fut>(policy: &RetryPolicy, mut op: F) -> Result<T, Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Err(e) if e.is_transient() && attempt < policy.max_attempts => {
+                time::delay_for(policy.backoff(attempt)).await;
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
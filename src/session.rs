@@ -1,26 +1,113 @@
 use crate::{
-    agent::Agent, aio::Aio, channel::Channel, into_the_future, listener::Listener, sftp::Sftp,
-    Error,
+    agent::Agent,
+    aio::Aio,
+    channel::{Channel, MAX_WRITE_SIZE},
+    error::LIBSSH2_ERROR_AUTHENTICATION_FAILED,
+    into_the_future,
+    knownhosts::KnownHosts,
+    listener::Listener,
+    sftp::Sftp,
+    util, Error,
 };
+use libssh2_sys as raw;
 use ssh2::{
-    self, DisconnectCode, HashType, HostKeyType, KeyboardInteractivePrompt, KnownHosts, MethodType,
+    self, DisconnectCode, HashType, HostKeyType, KeyboardInteractivePrompt, MethodType, Prompt,
     ScpFileStat,
 };
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, BorrowedFd};
 use std::{
     convert::From,
     future::Future,
     io,
-    net::TcpStream,
+    net::{SocketAddr, TcpStream},
+    os::raw::{c_char, c_int},
     path::Path,
     pin::Pin,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     task::{Context, Poll},
 };
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{self, ToSocketAddrs},
+    task,
+};
+
+bitflags::bitflags! {
+    /// Categories of libssh2 debug output, for [`Session::trace`].
+    pub struct TraceFlags: c_int {
+        /// Transport layer.
+        const TRANS = raw::LIBSSH2_TRACE_TRANS;
+        /// Key exchange.
+        const KEX = raw::LIBSSH2_TRACE_KEX;
+        /// User authentication.
+        const AUTH = raw::LIBSSH2_TRACE_AUTH;
+        /// Connection layer.
+        const CONN = raw::LIBSSH2_TRACE_CONN;
+        /// SCP transfers.
+        const SCP = raw::LIBSSH2_TRACE_SCP;
+        /// SFTP requests.
+        const SFTP = raw::LIBSSH2_TRACE_SFTP;
+        /// Errors.
+        const ERROR = raw::LIBSSH2_TRACE_ERROR;
+        /// Public key subsystem.
+        const PUBLICKEY = raw::LIBSSH2_TRACE_PUBLICKEY;
+        /// Socket reads and writes.
+        const SOCKET = raw::LIBSSH2_TRACE_SOCKET;
+    }
+}
 
 /// See [`Session`](ssh2::Session).
+///
+/// The underlying libssh2 session is always kept in non-blocking mode: every
+/// future in this crate relies on libssh2 returning `EAGAIN` so that it can
+/// wait on the socket instead of stalling the executor thread. See
+/// [`set_blocking`](Session::set_blocking).
+///
+/// libssh2 keeps the state of an unfinished request in the session, so a
+/// future from this crate that is dropped after it has started talking to the
+/// server, for example by `tokio::select!` or the [session
+/// timeout](Session::set_timeout), leaves the session unusable. Instead of
+/// failing later with protocol errors, every later async method on the
+/// session, its channels and its sftp handles fails with [`Error::Poisoned`];
+/// see [`is_poisoned`](Session::is_poisoned). Futures that have not been
+/// polled yet, or that have completed, can be dropped freely. Reads through
+/// `AsyncRead` can be abandoned at any time, while a write through
+/// `AsyncWrite` that returned `Pending` must be retried with the same data,
+/// as `write_all` does.
+///
+/// # Threading
+///
+/// `Session`, [`Channel`], [`Sftp`] and [`File`](crate::File) are `Send` and
+/// `Sync`, and so are the futures returned by their methods, so they can be
+/// moved into spawned tasks. libssh2 still handles one request at a time per
+/// session, though, so two tasks must not await methods of the same session,
+/// or of handles opened from it, at the same time. Nothing detects this; it
+/// shows up as protocol errors. Streaming through `AsyncRead` and
+/// `AsyncWrite`, which needs `&mut` access to a single handle, can overlap
+/// with other work. To run requests in parallel, give each task its own
+/// session, for example from a [`SessionPool`](crate::SessionPool).
+///
+/// # Authentication
+///
+/// A failed `userauth_*` call, whether the server rejected the credentials
+/// with [`Error::AuthFailed`] or a key file could not be loaded, leaves the
+/// session ready for another attempt, so methods can be tried in turn on one
+/// session. A new session and handshake are only needed when the attempt was
+/// cut short, leaving the session [poisoned](Session::is_poisoned), or when
+/// the server hangs up after too many failures, which OpenSSH does after
+/// `MaxAuthTries` (six by default) rejected attempts. Each rejected public key
+/// counts, including every key [`userauth_agent`](Session::userauth_agent)
+/// offers. [`auth_methods`](Session::auth_methods) lists the methods still
+/// worth trying.
 pub struct Session {
     inner: ssh2::Session,
     aio: Arc<Option<Aio>>,
+    compress: AtomicBool,
 }
 
 impl Session {
@@ -31,13 +118,85 @@ impl Session {
         Ok(Self {
             inner: session,
             aio: Arc::new(None),
+            compress: AtomicBool::new(false),
         })
     }
 
+    /// Connects to `addr` and performs the handshake, returning a session
+    /// that is ready to authenticate.
+    ///
+    /// The address is resolved without blocking and each resolved address is
+    /// tried in turn. libssh2 needs a std [`TcpStream`], so the TCP connection
+    /// itself is opened on tokio's blocking thread pool; use
+    /// [`connect_stream`](Session::connect_stream) to set socket options or
+    /// to connect some other way.
+    ///
+    /// IPv6 addresses work like IPv4 ones, written as `"[::1]:22"` or
+    /// `("::1", 22)`. Link-local addresses need the interface they are
+    /// reached through: pass a [`SocketAddrV6`](std::net::SocketAddrV6)
+    /// with its scope id set, or a host and port pair such as
+    /// `("fe80::1%eth0", 22)`, which the system resolver understands. The
+    /// bracketed form `"[fe80::1%eth0]:22"` is not accepted by std's
+    /// address parsing and fails to resolve.
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<Session, Error> {
+        let addrs: Vec<SocketAddr> = net::lookup_host(addr).await?.collect();
+        let stream = task::spawn_blocking(move || TcpStream::connect(&addrs[..]))
+            .await
+            .map_err(io::Error::other)??;
+        Self::connect_stream(stream).await
+    }
+
+    /// Like [`connect`](Session::connect), but checks the server's host key
+    /// against `expected_fingerprint` before returning.
+    ///
+    /// The fingerprint is the SHA256 one that OpenSSH prints, such as
+    /// `SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s`; the `SHA256:`
+    /// prefix and any base64 padding are optional. On a mismatch the
+    /// connection is closed before any credentials are sent, and
+    /// [`Error::HostKeyMismatch`] reports both fingerprints.
+    pub async fn connect_verified<A: ToSocketAddrs>(
+        addr: A,
+        expected_fingerprint: &str,
+    ) -> Result<Session, Error> {
+        let sess = Self::connect(addr).await?;
+        let actual = sess
+            .host_key_fingerprint()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "server sent no host key"))?;
+        let expected = expected_fingerprint
+            .strip_prefix("SHA256:")
+            .unwrap_or(expected_fingerprint)
+            .trim_end_matches('=');
+        if actual["SHA256:".len()..] != *expected {
+            let _ = sess
+                .disconnect(
+                    Some(DisconnectCode::HostKeyNotVerifiable),
+                    "host key mismatch",
+                    None,
+                )
+                .await;
+            return Err(Error::HostKeyMismatch {
+                expected: expected_fingerprint.to_owned(),
+                actual,
+            });
+        }
+        Ok(sess)
+    }
+
+    /// Performs the handshake over an already connected `stream`, returning
+    /// a session that is ready to authenticate.
+    pub async fn connect_stream(stream: TcpStream) -> Result<Session, Error> {
+        let mut sess = Self::new()?;
+        sess.set_tcp_stream(stream)?;
+        sess.handshake().await?;
+        Ok(sess)
+    }
+
     /// See [`set_banner`](ssh2::Session::set_banner).
-    pub async fn set_banner(&self, banner: &str) -> Result<(), Error> {
-        let aio = self.aio.clone();
-        into_the_future!(aio; &mut || { self.inner.set_banner(banner) })
+    ///
+    /// This only stores the banner for the next handshake and never touches
+    /// the network, so it does not need to be awaited.
+    pub fn set_banner(&self, banner: &str) -> Result<(), Error> {
+        self.inner.set_banner(banner).map_err(From::from)
     }
 
     /// See [`set_allow_sigpipe`](ssh2::Session::set_allow_sigpipe).
@@ -45,17 +204,99 @@ impl Session {
         self.inner.set_allow_sigpipe(block)
     }
 
-    /// See [`set_allow_sigpipe`](ssh2::Session::set_compress).
+    /// See [`set_compress`](ssh2::Session::set_compress).
+    ///
+    /// Must be called before [`handshake`](Session::handshake). Whether the
+    /// server agreed can be checked afterwards with
+    /// [`methods`](Session::methods) for [`CompCs`](MethodType::CompCs) and
+    /// [`CompSc`](MethodType::CompSc).
     pub fn set_compress(&self, compress: bool) {
+        self.compress.store(compress, Ordering::Relaxed);
         self.inner.set_compress(compress)
     }
 
+    /// Returns whether compression was requested with
+    /// [`set_compress`](Session::set_compress).
+    pub fn compress(&self) -> bool {
+        self.compress.load(Ordering::Relaxed)
+    }
+
     /// See [`is_blocking`](ssh2::Session::is_blocking).
     pub fn is_blocking(&self) -> bool {
         self.inner.is_blocking()
     }
 
+    /// See [`set_blocking`](ssh2::Session::set_blocking).
+    ///
+    /// Blocking mode is refused with an [`io::ErrorKind::InvalidInput`] error
+    /// once a tcp stream is set, since a blocking libssh2 call would hang the
+    /// executor thread, and with it the futures that drive the session.
+    /// [`set_tcp_stream`](Session::set_tcp_stream) switches the session back
+    /// to non-blocking mode.
+    pub fn set_blocking(&self, blocking: bool) -> Result<(), Error> {
+        if blocking && self.aio.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a session driven by async io cannot be made blocking",
+            )
+            .into());
+        }
+        self.inner.set_blocking(blocking);
+        Ok(())
+    }
+
+    /// Returns whether an operation was cancelled partway through, leaving
+    /// the session unusable. See the [type-level docs](Session).
+    pub fn is_poisoned(&self) -> bool {
+        (*self.aio).as_ref().is_some_and(Aio::is_poisoned)
+    }
+
+    /// Returns the wrapped [`ssh2::Session`], for libssh2 features this crate
+    /// does not wrap yet. Available with the `raw-access` feature.
+    ///
+    /// The session is in non-blocking mode, so its calls fail with
+    /// `WouldBlock` instead of waiting for the server. Such a call has to be
+    /// repeated until it completes before the session is used for anything
+    /// else. Switching it to blocking mode or replacing its tcp stream
+    /// breaks the futures of this crate, so don't.
+    #[cfg(feature = "raw-access")]
+    pub fn raw(&self) -> &ssh2::Session {
+        &self.inner
+    }
+
+    /// Returns the code and message of the last error libssh2 recorded for
+    /// this session, or `None` if there is none.
+    ///
+    /// The message is often more specific than the [`Error`] a failed call
+    /// returned. libssh2 keeps it until the next failure overwrites it or
+    /// [`clear_last_error`](Session::clear_last_error) resets it, so it can
+    /// be stale if the last call succeeded. `EAGAIN`, which libssh2 records
+    /// whenever a call has to wait for the socket, is not reported.
+    pub fn last_error(&self) -> Option<(i32, String)> {
+        ssh2::Error::last_error(&self.inner)
+            .filter(|e| e.code() != raw::LIBSSH2_ERROR_EAGAIN)
+            .map(|e| (e.code(), e.message().to_owned()))
+    }
+
+    /// Resets the error returned by [`last_error`](Session::last_error).
+    pub fn clear_last_error(&self) {
+        let mut session = self.inner.raw();
+        unsafe {
+            libssh2_session_set_last_error(&mut *session, 0, b"\0".as_ptr() as *const c_char);
+        }
+    }
+
     /// See [`set_timeout`](ssh2::Session::set_timeout).
+    ///
+    /// The session is always driven in non-blocking mode, so the timeout is
+    /// enforced with a tokio timer instead: once a tcp stream is set, any
+    /// future returned by this crate that does not complete within the
+    /// timeout resolves to [`Error::Timeout`]. Reads and writes through
+    /// `AsyncRead` and `AsyncWrite` are not covered; see
+    /// [`Channel::set_read_timeout`] and
+    /// [`File::set_read_timeout`](crate::File::set_read_timeout) for
+    /// those. A value of 0 disables the timeout. To bound a single call
+    /// instead, wrap it in [`timeout`](crate::timeout).
     pub fn set_timeout(&self, timeout_ms: u32) {
         self.inner.set_timeout(timeout_ms)
     }
@@ -65,6 +306,32 @@ impl Session {
         self.inner.timeout()
     }
 
+    /// Enables libssh2's debug output for the categories in `bitmask`; an
+    /// empty mask turns it off again.
+    ///
+    /// libssh2 only produces this output when it was built with debugging
+    /// enabled, as the bundled copy is in debug builds; otherwise this does
+    /// nothing. The messages go to stderr, or to the [`log`] crate at debug
+    /// level with target `libssh2` when the `log` feature is enabled.
+    ///
+    /// [`log`]: https://docs.rs/log
+    pub fn trace(&self, bitmask: TraceFlags) -> Result<(), Error> {
+        let mut session = self.inner.raw();
+        let rc = unsafe { raw::libssh2_trace(&mut *session, bitmask.bits()) };
+        if rc < 0 {
+            return Err(ssh2::Error::from_errno(rc).into());
+        }
+        #[cfg(feature = "log")]
+        unsafe {
+            trace_log::libssh2_trace_sethandler(
+                &mut *session,
+                std::ptr::null_mut(),
+                trace_log::handler,
+            );
+        }
+        Ok(())
+    }
+
     /// See [`handshake`](ssh2::Session::handshake).
     pub async fn handshake(&mut self) -> Result<(), Error> {
         let aio = self.aio.clone();
@@ -75,29 +342,60 @@ impl Session {
     pub fn set_tcp_stream(&mut self, stream: TcpStream) -> Result<(), Error> {
         let aio = Aio::new(stream.try_clone()?, self.inner.clone())?;
         self.aio = Arc::new(Some(aio));
+        self.inner.set_blocking(false);
         self.inner.set_tcp_stream(stream);
         Ok(())
     }
 
+    /// Uses the socket behind `stream` as the transport, for streams that
+    /// were connected by other means, such as through a SOCKS proxy.
+    ///
+    /// libssh2 reads and writes the file descriptor directly, so `stream`
+    /// must be a connected stream socket (TCP or Unix domain) with no data
+    /// buffered on its side: anything a proxy library has already read past
+    /// its own handshake is lost. The descriptor is duplicated and `stream`
+    /// is dropped; any [`AsyncRead`](tokio::io::AsyncRead) +
+    /// [`AsyncWrite`](tokio::io::AsyncWrite) stream that implements
+    /// [`AsRawFd`] works. Call [`handshake`](Session::handshake) next.
+    #[cfg(unix)]
+    pub fn set_stream<S: AsRawFd>(&mut self, stream: S) -> Result<(), Error> {
+        // Safe because `stream` keeps the descriptor open while it is
+        // duplicated.
+        let fd = unsafe { BorrowedFd::borrow_raw(stream.as_raw_fd()) }.try_clone_to_owned()?;
+        drop(stream);
+        self.set_tcp_stream(TcpStream::from(fd))
+    }
+
     /// See [`userauth_password`](ssh2::Session::userauth_password).
     pub async fn userauth_password(&self, username: &str, password: &str) -> Result<(), Error> {
         let aio = self.aio.clone();
         into_the_future!(aio; &mut || { self.inner.userauth_password(username, password) })
+            .map_err(Error::auth_failed("password"))
     }
 
     /// See [`userauth_keyboard_interactive`](ssh2::Session::userauth_keyboard_interactive).
-    pub fn userauth_keyboard_interactive<P: KeyboardInteractivePrompt>(
+    ///
+    /// `prompter` is called once for every challenge the server sends and
+    /// must return one response per prompt.
+    pub async fn userauth_keyboard_interactive<F>(
         &self,
-        _username: &str,
-        _prompter: &mut P,
-    ) -> Result<(), Error> {
-        unimplemented!();
+        username: &str,
+        prompter: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(&[Prompt<'_>]) -> Vec<String>,
+    {
+        let aio = self.aio.clone();
+        let mut prompter = FnPrompt(prompter);
+        into_the_future!(aio; &mut || { self.inner.userauth_keyboard_interactive(username, &mut prompter) })
+            .map_err(Error::auth_failed("keyboard-interactive"))
     }
 
     /// See [`userauth_agent`](ssh2::Session::userauth_agent).
     pub async fn userauth_agent(&self, username: &str) -> Result<(), Error> {
         let aio = self.aio.clone();
         into_the_future!(aio; &mut || { self.inner.userauth_agent(username) })
+            .map_err(Error::auth_failed("publickey"))
     }
 
     /// See [`userauth_pubkey_file`](ssh2::Session::userauth_pubkey_file).
@@ -110,6 +408,7 @@ impl Session {
     ) -> Result<(), Error> {
         let aio = self.aio.clone();
         into_the_future!(aio; &mut || { self.inner.userauth_pubkey_file(username, pubkey, privatekey, passphrase) })
+            .map_err(Error::auth_failed("publickey"))
     }
 
     /// See [`userauth_pubkey_memory`](ssh2::Session::userauth_pubkey_memory).
@@ -123,6 +422,46 @@ impl Session {
     ) -> Result<(), Error> {
         let aio = self.aio.clone();
         into_the_future!(aio; &mut || { self.inner.userauth_pubkey_memory(username, pubkeydata, privatekeydata, passphrase) })
+            .map_err(Error::auth_failed("publickey"))
+    }
+
+    /// Authenticates with an OpenSSH user certificate, read from `cert`
+    /// (the `-cert.pub` file), and the matching private key.
+    ///
+    /// libssh2 only supports ECDSA certificates (`ecdsa-sha2-nistp*-cert-v01`);
+    /// others fail with [`io::ErrorKind::Unsupported`] before anything is
+    /// sent, and a `cert` that holds a plain public key fails with
+    /// [`io::ErrorKind::InvalidInput`]. Certificates of any type work through
+    /// [`userauth_agent`](Session::userauth_agent) once they have been added
+    /// to the agent together with their key.
+    pub async fn userauth_cert_file(
+        &self,
+        username: &str,
+        cert: &Path,
+        privatekey: &Path,
+        passphrase: Option<&str>,
+    ) -> Result<(), Error> {
+        let data = fs::read_to_string(cert)
+            .await
+            .map_err(Error::with_path(cert))?;
+        check_cert(&data).map_err(Error::with_path(cert))?;
+        self.userauth_pubkey_file(username, Some(cert), privatekey, passphrase)
+            .await
+    }
+
+    /// Like [`userauth_cert_file`](Session::userauth_cert_file), with the
+    /// contents of the `-cert.pub` file and of the private key file.
+    #[cfg(unix)]
+    pub async fn userauth_cert_memory(
+        &self,
+        username: &str,
+        cert: &str,
+        privatekey: &str,
+        passphrase: Option<&str>,
+    ) -> Result<(), Error> {
+        check_cert(cert)?;
+        self.userauth_pubkey_memory(username, Some(cert), privatekey, passphrase)
+            .await
     }
 
     /// See [`userauth_hostbased_file`](ssh2::Session::userauth_hostbased_file).
@@ -138,6 +477,25 @@ impl Session {
     ) -> Result<(), Error> {
         let aio = self.aio.clone();
         into_the_future!(aio; &mut || { self.inner.userauth_hostbased_file(username, publickey, privatekey, passphrase, hostname, local_username) })
+            .map_err(Error::auth_failed("hostbased"))
+    }
+
+    /// Attempts the `none` authentication method.
+    ///
+    /// libssh2 has no separate call for this; it is the request
+    /// [`auth_methods`](Session::auth_methods) sends, so this succeeds if the
+    /// server let the user in without credentials and fails with
+    /// [`Error::AuthFailed`] otherwise.
+    pub async fn userauth_none(&self, username: &str) -> Result<(), Error> {
+        self.auth_methods(username).await?;
+        if self.authenticated() {
+            Ok(())
+        } else {
+            Err(Error::AuthFailed(
+                "none",
+                ssh2::Error::from_errno(LIBSSH2_ERROR_AUTHENTICATION_FAILED),
+            ))
+        }
     }
 
     /// See [`authenticated`](ssh2::Session::authenticated).
@@ -152,12 +510,20 @@ impl Session {
     }
 
     /// See [`method_pref`](ssh2::Session::method_pref).
-    pub async fn method_pref(&self, method_type: MethodType, prefs: &str) -> Result<(), Error> {
-        let aio = self.aio.clone();
-        into_the_future!(aio; &mut || { self.inner.method_pref(method_type, prefs) })
+    ///
+    /// Must be called before [`handshake`](Session::handshake). Only the
+    /// listed algorithms are offered, so if the server supports none of them
+    /// the handshake fails with `LIBSSH2_ERROR_KEX_FAILURE` (-5) instead of
+    /// falling back to another algorithm.
+    pub fn method_pref(&self, method_type: MethodType, prefs: &str) -> Result<(), Error> {
+        self.inner
+            .method_pref(method_type, prefs)
+            .map_err(From::from)
     }
 
     /// See [`methods`](ssh2::Session::methods).
+    ///
+    /// Returns `None` until the handshake has negotiated the algorithms.
     pub fn methods(&self, method_type: MethodType) -> Option<&str> {
         self.inner.methods(method_type)
     }
@@ -175,7 +541,8 @@ impl Session {
 
     /// See [`known_hosts`](ssh2::Session::known_hosts).
     pub fn known_hosts(&self) -> Result<KnownHosts, Error> {
-        self.inner.known_hosts().map_err(From::from)
+        let known_hosts = self.inner.known_hosts()?;
+        Ok(KnownHosts::new(known_hosts, self.inner.clone()))
     }
 
     /// See [`channel_session`](ssh2::Session::channel_session).
@@ -185,6 +552,42 @@ impl Session {
         Ok(Channel::new(channel, self.aio.clone()))
     }
 
+    /// Like [`channel_session`](Session::channel_session), but advertises a
+    /// receive window of `window_size` bytes and accepts packets of up to
+    /// `packet_size` bytes instead of the defaults of 2 MiB and 32 KiB.
+    ///
+    /// The server stops sending once it has filled the window and waits for
+    /// libssh2 to open it again as data is read, so a channel cannot receive
+    /// faster than one window per round trip: 2 MiB at a 200 ms round trip
+    /// caps it near 10 MiB/s. Size the window to at least the bandwidth
+    /// times the round trip time. Keep `packet_size` at 32 KiB or below:
+    /// libssh2 fails the whole session on packets over 40000 bytes. The
+    /// window only governs what the server sends; how much this side may
+    /// send is up to the server.
+    ///
+    /// [`sftp`](Session::sftp) channels are opened by libssh2 with the
+    /// default window, which sftp reads then grow to fit their read-ahead;
+    /// see [`BufferedReader`](crate::BufferedReader).
+    pub async fn channel_session_with_window(
+        &self,
+        window_size: u32,
+        packet_size: u32,
+    ) -> Result<Channel, Error> {
+        self.channel_open("session", window_size, packet_size, None)
+            .await
+    }
+
+    /// Opens a session channel and requests the subsystem `name` on it, for
+    /// example `netconf`; see [`Channel::subsystem`].
+    ///
+    /// If the server refuses the subsystem the channel is dropped and the
+    /// error returned.
+    pub async fn channel_subsystem(&self, name: &str) -> Result<Channel, Error> {
+        let mut channel = self.channel_session().await?;
+        channel.subsystem(name).await?;
+        Ok(channel)
+    }
+
     /// See [`channel_direct_tcpip`](ssh2::Session::channel_direct_tcpip).
     pub async fn channel_direct_tcpip(
         &self,
@@ -218,6 +621,11 @@ impl Session {
     }
 
     /// See [`scp_send`](ssh2::Session::scp_send).
+    ///
+    /// Once all data has been written, finish the transfer with
+    /// [`send_eof`](Channel::send_eof), [`wait_eof`](Channel::wait_eof),
+    /// [`close`](Channel::close) and [`wait_close`](Channel::wait_close) so
+    /// that the remote has stored the file before the channel is dropped.
     pub async fn scp_send(
         &self,
         remote_path: &Path,
@@ -231,11 +639,73 @@ impl Session {
         Ok(Channel::new(channel, self.aio.clone()))
     }
 
+    /// Copies the local file `local` to `remote` over scp, creating it with
+    /// `mode`, and returns the number of bytes sent.
+    ///
+    /// The file is read in large blocks and sent as full-sized channel
+    /// packets, which is what keeps scp fast: a copy through the 2 KiB
+    /// buffer of [`tokio::io::copy`] puts every 2 KiB into a packet of its
+    /// own. The transfer is finished as described for
+    /// [`scp_send`](Session::scp_send), so the remote has stored the file
+    /// when this returns. A local file that changes size while it is read
+    /// fails the upload, since scp announces the size up front. Failures
+    /// are reported as [`Error::Path`] naming the file involved.
+    pub async fn scp_upload(&self, local: &Path, remote: &Path, mode: i32) -> Result<u64, Error> {
+        let mut src = fs::File::open(local)
+            .await
+            .map_err(Error::with_path(local))?;
+        let size = src.metadata().await.map_err(Error::with_path(local))?.len();
+        let mut dst = self
+            .scp_send(remote, mode, size, None)
+            .await
+            .map_err(Error::with_path(remote))?;
+
+        // Fill whole blocks, as tokio's blocking reads return at most 16 KiB.
+        let mut buf = vec![0; 8 * MAX_WRITE_SIZE];
+        let mut sent = 0;
+        loop {
+            let mut filled = 0;
+            while filled < buf.len() {
+                match src.read(&mut buf[filled..]).await {
+                    Ok(0) => break,
+                    Ok(n) => filled += n,
+                    Err(e) => return Err(Error::with_path(local)(e)),
+                }
+            }
+            if filled == 0 {
+                break;
+            }
+            sent += filled as u64;
+            if sent > size {
+                break;
+            }
+            dst.write_all(&buf[..filled])
+                .await
+                .map_err(Error::with_path(remote))?;
+        }
+        if sent != size {
+            return Err(Error::with_path(local)(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "file changed size during upload",
+            )));
+        }
+
+        async {
+            dst.send_eof().await?;
+            dst.wait_eof().await?;
+            dst.close().await?;
+            dst.wait_close().await
+        }
+        .await
+        .map_err(Error::with_path(remote))?;
+        Ok(sent)
+    }
+
     /// See [`sftp`](ssh2::Session::sftp).
     pub async fn sftp(&self) -> Result<Sftp, Error> {
         let aio = self.aio.clone();
         let sftp = into_the_future!(aio; &mut || { self.inner.sftp() })?;
-        Ok(Sftp::new(sftp, self.aio.clone()))
+        Ok(Sftp::new(sftp, self.inner.clone(), self.aio.clone()))
     }
 
     /// See [`channel_open`](ssh2::Session::channel_open).
@@ -252,6 +722,9 @@ impl Session {
     }
 
     /// See [`banner`](ssh2::Session::banner).
+    ///
+    /// Returns the identification string the server sent during the
+    /// handshake, or `None` before the handshake has completed.
     pub fn banner(&self) -> Option<&str> {
         self.inner.banner()
     }
@@ -271,6 +744,14 @@ impl Session {
         self.inner.host_key_hash(hash)
     }
 
+    /// Returns the SHA256 fingerprint of the server's host key in OpenSSH's
+    /// format, such as `SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s`,
+    /// or `None` before the handshake.
+    pub fn host_key_fingerprint(&self) -> Option<String> {
+        self.host_key_hash(HashType::Sha256)
+            .map(|hash| format!("SHA256:{}", util::base64(hash)))
+    }
+
     /// See [`set_keepalive`](ssh2::Session::set_keepalive).
     pub fn set_keepalive(&self, want_reply: bool, interval: u32) {
         self.inner.set_keepalive(want_reply, interval)
@@ -282,7 +763,30 @@ impl Session {
         into_the_future!(aio; &mut || { self.inner.keepalive_send() })
     }
 
+    /// Returns whether the connection looks usable, without waiting for the
+    /// server.
+    ///
+    /// The session must have a tcp stream and not be
+    /// [poisoned](Session::is_poisoned), the server must not have closed the
+    /// socket, and [`keepalive_send`](Session::keepalive_send) must succeed.
+    /// That catches servers that hung up, including ones that timed out an
+    /// idle session. A connection that died silently, say in a NAT, is only
+    /// noticed once an earlier keepalive has failed to get through, which
+    /// needs [`set_keepalive`](Session::set_keepalive) and can take minutes.
+    pub async fn is_alive(&self) -> bool {
+        match &*self.aio {
+            Some(aio) if !aio.is_poisoned() && aio.is_connected() => {
+                self.keepalive_send().await.is_ok()
+            }
+            _ => false,
+        }
+    }
+
     /// See [`disconnect`](ssh2::Session::disconnect).
+    ///
+    /// Sends `SSH_MSG_DISCONNECT` so the server logs `description` rather
+    /// than a dropped connection. A `reason` of `None` is sent as
+    /// [`ByApplication`](DisconnectCode::ByApplication).
     pub async fn disconnect(
         &self,
         reason: Option<DisconnectCode>,
@@ -293,3 +797,78 @@ impl Session {
         into_the_future!(aio; &mut || { self.inner.disconnect(reason, description, lang) })
     }
 }
+
+struct FnPrompt<F>(F);
+
+impl<F> KeyboardInteractivePrompt for FnPrompt<F>
+where
+    F: FnMut(&[Prompt<'_>]) -> Vec<String>,
+{
+    fn prompt<'a>(
+        &mut self,
+        _username: &str,
+        _instructions: &str,
+        prompts: &[Prompt<'a>],
+    ) -> Vec<String> {
+        (self.0)(prompts)
+    }
+}
+
+// Not bound by libssh2-sys.
+extern "C" {
+    fn libssh2_session_set_last_error(
+        session: *mut raw::LIBSSH2_SESSION,
+        errcode: c_int,
+        errmsg: *const c_char,
+    ) -> c_int;
+}
+
+#[cfg(feature = "log")]
+mod trace_log {
+    use libssh2_sys::LIBSSH2_SESSION;
+    use std::{
+        os::raw::{c_char, c_int, c_void},
+        slice,
+    };
+
+    // Not bound by libssh2-sys.
+    extern "C" {
+        pub fn libssh2_trace_sethandler(
+            session: *mut LIBSSH2_SESSION,
+            context: *mut c_void,
+            callback: extern "C" fn(*mut LIBSSH2_SESSION, *mut c_void, *const c_char, usize),
+        ) -> c_int;
+    }
+
+    pub extern "C" fn handler(
+        _session: *mut LIBSSH2_SESSION,
+        _context: *mut c_void,
+        message: *const c_char,
+        len: usize,
+    ) {
+        let message = unsafe { slice::from_raw_parts(message as *const u8, len) };
+        log::debug!(target: "libssh2", "{}", String::from_utf8_lossy(message).trim_end());
+    }
+}
+
+/// Checks that `cert`, the contents of a `-cert.pub` file, is a certificate
+/// of a type libssh2 can authenticate with.
+fn check_cert(cert: &str) -> Result<(), io::Error> {
+    let kind = cert.split_whitespace().next().unwrap_or("");
+    if !kind.ends_with("-cert-v01@openssh.com") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "not an OpenSSH certificate",
+        ));
+    }
+    if !kind.starts_with("ecdsa-sha2-nistp") {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "libssh2 cannot authenticate with {} certificates, only ECDSA ones",
+                kind
+            ),
+        ));
+    }
+    Ok(())
+}
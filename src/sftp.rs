@@ -1,31 +1,494 @@
-use crate::{aio::Aio, into_the_future, Error};
-use ssh2::{self, FileStat, OpenFlags, OpenType};
+use crate::{
+    aio::{self, Aio, Deferred},
+    into_the_future,
+    progress::{self, ProgressReceiver},
+    raw_sftp::{self, RawSftp},
+    util::{RateLimit, ReadTimeout},
+    Error,
+};
+use bytes::Buf;
+use ssh2::{self, FileStat, FileType, OpenFlags, OpenType, RenameFlags};
 use std::{
+    cmp,
+    collections::HashSet,
     convert::From,
-    future::Future,
-    io::{self, Read, Write},
+    future::{self, Future},
+    io::{self, IoSlice, Read, Seek, SeekFrom, Write},
+    mem::{self, ManuallyDrop},
     path::{Path, PathBuf},
     pin::Pin,
-    sync::Arc,
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
     task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    fs,
+    io::{AsyncBufRead, AsyncRead, AsyncReadExt, AsyncSeek, AsyncWrite, AsyncWriteExt},
+    stream::Stream,
+};
+
+/// Size of the buffer used when streaming files to and from the remote.
+const BUFFER_SIZE: usize = 32 * 1024;
+
+/// `LIBSSH2_ERROR_FILE`, which [`ssh2::File::readdir`] reports once the end
+/// of the directory has been reached. libssh2 never returns it from readdir
+/// itself: failures reported by the server, such as a permission error, come
+/// back as `LIBSSH2_ERROR_SFTP_PROTOCOL` instead.
+const LIBSSH2_ERROR_FILE: i32 = -16;
+
+/// `LIBSSH2_ERROR_SFTP_PROTOCOL`, which libssh2 reports for every failure
+/// status from the server, whatever its code.
+const LIBSSH2_ERROR_SFTP_PROTOCOL: i32 = -31;
+
+/// OpenSSH's extensions for hard links, server-side copies, fsync and
+/// filesystem statistics.
+const HARDLINK: &[u8] = b"hardlink@openssh.com";
+const COPY_DATA: &[u8] = b"copy-data";
+const FSYNC: &[u8] = b"fsync@openssh.com";
+const STATVFS: &[u8] = b"statvfs@openssh.com";
+
+/// Longest chain of symbolic links [`Sftp::resolve`] follows, as on Linux.
+const MAX_SYMLINKS: usize = 40;
+
+/// A [`FileStat`] that makes `setstat` change nothing.
+const UNCHANGED: FileStat = FileStat {
+    size: None,
+    uid: None,
+    gid: None,
+    perm: None,
+    atime: None,
+    mtime: None,
 };
-use tokio::io::{AsyncRead, AsyncWrite};
 
 /// See [`Sftp`](ssh2::Sftp).
+///
+/// An `Sftp` and the files opened from it share the request state of their
+/// session; see [threading](crate::Session#threading) for how they may be
+/// used across tasks. Like a dropped [`File`], a dropped `Sftp` is shut down
+/// before the next request on the session rather than with the blocking
+/// round trip `ssh2` would use.
+///
+/// # Protocol version
+///
+/// libssh2 offers sftp version 3 and accepts whatever lower version the
+/// server answers with, so every connection speaks v3 or older; OpenSSH
+/// and most other servers settle on v3. libssh2 does not report the
+/// negotiated version, and there is nothing to detect: the behaviour below
+/// is the same for every server.
+///
+/// - [`RenameFlags`](ssh2::RenameFlags) are only sent from v5 on, so
+///   libssh2 never sends them and [`rename`](Sftp::rename) follows the
+///   server's v3 rules. OpenSSH refuses to rename over an existing file;
+///   use [`rename_overwrite`](Sftp::rename_overwrite) or
+///   [`rename_noclobber`](Sftp::rename_noclobber) for a defined outcome.
+/// - libssh2 does not support extensions such as OpenSSH's
+///   `posix-rename@openssh.com`, apart from the `fsync@openssh.com` behind
+///   [`File::fsync`]. [`hardlink`](Sftp::hardlink), [`copy`](Sftp::copy)
+///   and [`sync_dir`](Sftp::sync_dir) are the exceptions: they send their
+///   extension requests on a channel of their own.
+/// - Servers from before v2 have no rename at all; it fails with an
+///   [`Error::SSH2`] protocol error.
 pub struct Sftp {
-    inner: ssh2::Sftp,
+    inner: ManuallyDrop<ssh2::Sftp>,
+    session: ssh2::Session,
     aio: Arc<Option<Aio>>,
 }
 
 /// See [`File`](ssh2::File).
+///
+/// The [`AsyncBufRead`] impl reads ahead 32 KiB at a time; wrap the file in a
+/// [`BufferedReader`] to keep a larger window of reads in flight, or in a
+/// [`BufferedWriter`] to send many small writes as fewer requests.
+///
+/// To read a byte range, seek to its start and limit the reads with
+/// [`AsyncReadExt::take`], on `&mut file` to keep the file. Afterwards the
+/// position is just past the bytes read, so reading on continues after the
+/// range. libssh2 may have asked the server for up to four times the size
+/// of the last read beyond it, which the next seek discards.
+///
+/// Prefer [`close`](File::close), which reports errors. `ssh2` closes a
+/// dropped handle with a blocking round trip, so a `File` dropped without
+/// being closed is instead closed at the start of the next request on its
+/// session, which keeps to one request at a time, and any error is
+/// discarded. Handles still open when the session and everything opened
+/// from it are gone are closed by a task spawned on the current tokio
+/// runtime. A handle is leaked, along with the memory of its session, when
+/// it cannot be closed that way: outside a runtime, after the session was
+/// [poisoned](crate::Session::is_poisoned), or once closing it has failed,
+/// since libssh2 frees a handle even when closing it fails.
 pub struct File {
-    inner: ssh2::File,
+    inner: ManuallyDrop<ssh2::File>,
     aio: Arc<Option<Aio>>,
+    closed: bool,
+    // Closing failed, after which libssh2 has freed the handle but ssh2
+    // would still close it on drop.
+    close_failed: bool,
+    seek: Option<SeekFrom>,
+    read_timeout: ReadTimeout,
+    read_chunk_size: Option<usize>,
+    rate_limit: RateLimit,
+    sync_on_flush: bool,
+    // Bytes were written since the last fsync.
+    unsynced: bool,
+    transferred: u64,
+    // Read ahead by `AsyncBufRead`; `pos..filled` has not been consumed yet.
+    buf: Vec<u8>,
+    pos: usize,
+    filled: usize,
+}
+
+/// A buffered reader over a [`File`] that keeps several SFTP read requests
+/// in flight.
+///
+/// libssh2 splits each read into requests of at most 30000 bytes and, when
+/// given a large buffer, asks for up to four times the buffer size ahead of
+/// what has been consumed. `BufferedReader` always reads into its full
+/// internal buffer, so its capacity controls how much data is requested ahead
+/// of the consumer. Pick it close to the bandwidth-delay product of the link.
+pub struct BufferedReader {
+    file: File,
+    buf: Box<[u8]>,
+    pos: usize,
+    filled: usize,
+}
+
+/// A buffered writer over a [`File`] that gathers small writes into larger
+/// SFTP write requests.
+///
+/// Every write to a [`File`] is sent as at least one SFTP request, so many
+/// small writes, such as a log written a line at a time, spend most of their
+/// time waiting for the server. `BufferedWriter` collects writes until its
+/// buffer is full and sends them together. Buffered data is sent once the
+/// buffer fills up and on `poll_flush`, `poll_shutdown` and
+/// [`close`](BufferedWriter::close); whatever is still buffered when the
+/// writer is dropped is lost.
+pub struct BufferedWriter {
+    file: File,
+    buf: Vec<u8>,
+    // `..written` has been acknowledged by the server.
+    written: usize,
+    capacity: usize,
+}
+
+/// Statistics about the remote filesystem containing a file, as returned by
+/// [`File::statvfs`] and [`Sftp::statvfs`].
+///
+/// The fields mirror those of `statvfs(3)`; block counts are in units of
+/// `frsize` bytes.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct StatVfs {
+    /// Filesystem block size.
+    pub bsize: u64,
+    /// Fragment size.
+    pub frsize: u64,
+    /// Size of the filesystem in `frsize` units.
+    pub blocks: u64,
+    /// Number of free blocks.
+    pub bfree: u64,
+    /// Number of free blocks for unprivileged users.
+    pub bavail: u64,
+    /// Number of inodes.
+    pub files: u64,
+    /// Number of free inodes.
+    pub ffree: u64,
+    /// Number of free inodes for unprivileged users.
+    pub favail: u64,
+    /// Filesystem ID.
+    pub fsid: u64,
+    /// Mount flags.
+    pub flag: u64,
+    /// Maximum filename length.
+    pub namemax: u64,
+}
+
+/// Entries of a remote directory, as returned by [`Sftp::read_dir_stream`].
+///
+/// Entries are read with [`next_entry`](ReadDir::next_entry), or through the
+/// [`Stream`] implementation, which yields the same items. The directory
+/// handle is closed once the last entry has been read.
+pub struct ReadDir {
+    dirname: PathBuf,
+    dir: Option<File>,
+    // The last entry has been read and `dir` is being closed.
+    exhausted: bool,
+}
+
+/// Metadata about a remote file, as returned by [`Sftp::stat`],
+/// [`Sftp::lstat`] and [`Sftp::readdir`].
+///
+/// This is a thin wrapper around [`FileStat`] with accessors modelled on
+/// [`std::fs::Metadata`]. Servers may omit any attribute, so the accessors
+/// fall back to `None` (or zero for [`len`](Metadata::len)) when one is
+/// missing.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Metadata {
+    stat: FileStat,
+}
+
+/// How [`Sftp::copy`] copied a file.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CopyMethod {
+    /// The server copied the data itself, with the `copy-data` extension.
+    ServerSide,
+    /// The data was read from the server and written back.
+    Streamed,
+}
+
+/// The order of the entries returned by [`Sftp::readdir_sorted`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SortBy {
+    /// By path, compared byte by byte.
+    Name,
+    /// Smallest first; entries of unknown size count as empty.
+    Size,
+    /// Oldest first; entries without a modification time come first.
+    Modified,
+}
+
+impl Metadata {
+    /// Returns the type of the file, derived from its permission bits.
+    pub fn file_type(&self) -> FileType {
+        self.stat.file_type()
+    }
+
+    /// Returns `true` if this metadata is for a directory.
+    pub fn is_dir(&self) -> bool {
+        self.file_type().is_dir()
+    }
+
+    /// Returns `true` if this metadata is for a regular file.
+    pub fn is_file(&self) -> bool {
+        self.file_type().is_file()
+    }
+
+    /// Returns `true` if this metadata is for a symbolic link.
+    ///
+    /// Only [`Sftp::lstat`] and [`Sftp::readdir`] report symlinks;
+    /// [`Sftp::stat`] follows them.
+    pub fn is_symlink(&self) -> bool {
+        self.file_type().is_symlink()
+    }
+
+    /// Returns the size of the file in bytes, or zero if the server did not
+    /// report it.
+    pub fn len(&self) -> u64 {
+        self.stat.size.unwrap_or(0)
+    }
+
+    /// Returns `true` if the file is empty or its size is unknown.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the permission bits of the file, without the file type.
+    pub fn permissions(&self) -> Option<u32> {
+        self.stat.perm.map(|perm| perm & 0o7777)
+    }
+
+    /// Returns the owner's user ID.
+    pub fn uid(&self) -> Option<u32> {
+        self.stat.uid
+    }
+
+    /// Returns the owner's group ID.
+    pub fn gid(&self) -> Option<u32> {
+        self.stat.gid
+    }
+
+    /// Returns the last modification time.
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.stat
+            .mtime
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    /// Returns the last access time.
+    pub fn accessed(&self) -> Option<SystemTime> {
+        self.stat
+            .atime
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    /// Returns the underlying attributes.
+    pub fn as_file_stat(&self) -> &FileStat {
+        &self.stat
+    }
+
+    /// Renders the file type and permission bits the way `ls -l` does, such
+    /// as `-rw-r--r--` or `drwxrwxrwt`.
+    ///
+    /// The setuid, setgid and sticky bits replace the matching execute
+    /// character with `s` or `t`, in upper case if that execute bit is not
+    /// set. If the server did not report the mode, all ten characters are
+    /// `?`.
+    pub fn mode_string(&self) -> String {
+        let perm = match self.stat.perm {
+            Some(perm) => perm,
+            None => return "?".repeat(10),
+        };
+        let kind = match perm & 0o170000 {
+            0o040000 => 'd',
+            0o120000 => 'l',
+            0o010000 => 'p',
+            0o020000 => 'c',
+            0o060000 => 'b',
+            0o140000 => 's',
+            0o100000 => '-',
+            _ => '?',
+        };
+        let mut mode = String::with_capacity(10);
+        mode.push(kind);
+        // (shift of the rwx triplet, special bit, its character)
+        for &(shift, special, ch) in &[(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+            let bits = perm >> shift;
+            mode.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+            mode.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+            mode.push(match (bits & 0o1 != 0, perm & special != 0) {
+                (true, true) => ch,
+                (false, true) => ch.to_ascii_uppercase(),
+                (true, false) => 'x',
+                (false, false) => '-',
+            });
+        }
+        mode
+    }
+
+    /// Renders a line of an `ls -l` style listing for the entry `name`: the
+    /// [mode](Metadata::mode_string), the owner and group IDs, the size and
+    /// the modification time in UTC, for example
+    ///
+    /// ```text
+    /// -rw-r--r--  1000  1000       4096 2020-01-31 12:00 notes.txt
+    /// ```
+    ///
+    /// Attributes the server did not report are shown as `?`. SFTP only
+    /// carries numeric IDs, so there are no user or group names, and the
+    /// target of a symlink needs a separate [`Sftp::readlink`].
+    pub fn long_format(&self, name: &Path) -> String {
+        fn or_unknown<T: ToString>(value: Option<T>) -> String {
+            value.map_or_else(|| "?".to_owned(), |value| value.to_string())
+        }
+        format!(
+            "{} {:>5} {:>5} {:>10} {:>16} {}",
+            self.mode_string(),
+            or_unknown(self.stat.uid),
+            or_unknown(self.stat.gid),
+            or_unknown(self.stat.size),
+            or_unknown(self.stat.mtime.map(format_utc)),
+            name.display()
+        )
+    }
+}
+
+/// Formats seconds since the Unix epoch as `YYYY-MM-DD HH:MM` in UTC.
+fn format_utc(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let minutes = secs % 86_400 / 60;
+    // Civil date from a day count, after Howard Hinnant's `civil_from_days`.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+impl ReadDir {
+    /// Returns the next entry, joined onto the directory path, or `None`
+    /// once the directory is exhausted. The `.` and `..` entries are skipped.
+    pub async fn next_entry(&mut self) -> Result<Option<(PathBuf, Metadata)>, Error> {
+        let timeout = self
+            .dir
+            .as_ref()
+            .and_then(|dir| (*dir.aio).as_ref().and_then(Aio::timeout));
+        let next = async {
+            future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx))
+                .await
+                .transpose()
+        };
+        match timeout {
+            Some(timeout) => crate::timeout(timeout, next).await,
+            None => next.await,
+        }
+    }
+}
+
+impl Stream for ReadDir {
+    type Item = Result<(PathBuf, Metadata), Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        while let Some(dir) = this.dir.as_mut() {
+            if this.exhausted {
+                let res = match Pin::new(&mut *dir).poll_shutdown(cx) {
+                    Poll::Ready(res) => res,
+                    Poll::Pending => return Poll::Pending,
+                };
+                this.dir = None;
+                return match res {
+                    Ok(()) => Poll::Ready(None),
+                    Err(e) => Poll::Ready(Some(Err(e.into()))),
+                };
+            }
+            match dir.inner.readdir() {
+                Ok((filename, stat)) => {
+                    if &*filename == Path::new(".") || &*filename == Path::new("..") {
+                        continue;
+                    }
+                    let entry = (this.dirname.join(&filename), Metadata::from(stat));
+                    return Poll::Ready(Some(Ok(entry)));
+                }
+                Err(e) if e.code() == LIBSSH2_ERROR_FILE => this.exhausted = true,
+                Err(e) => {
+                    let e = io::Error::from(e);
+                    if e.kind() != io::ErrorKind::WouldBlock {
+                        return Poll::Ready(Some(Err(e.into())));
+                    }
+                    if let Err(e) = aio::set_waker(&dir.aio, cx) {
+                        return Poll::Ready(Some(Err(e.into())));
+                    }
+                    return Poll::Pending;
+                }
+            }
+        }
+        Poll::Ready(None)
+    }
+}
+
+impl From<FileStat> for Metadata {
+    fn from(stat: FileStat) -> Self {
+        Self { stat }
+    }
+}
+
+impl From<Metadata> for FileStat {
+    fn from(metadata: Metadata) -> Self {
+        metadata.stat
+    }
 }
 
 impl Sftp {
-    pub(crate) fn new(sftp: ssh2::Sftp, aio: Arc<Option<Aio>>) -> Self {
-        Self { inner: sftp, aio }
+    pub(crate) fn new(sftp: ssh2::Sftp, session: ssh2::Session, aio: Arc<Option<Aio>>) -> Self {
+        Self {
+            inner: ManuallyDrop::new(sftp),
+            session,
+            aio,
+        }
     }
 
     /// See [`open_mode`](ssh2::Sftp::open_mode).
@@ -58,6 +521,27 @@ impl Sftp {
         .await
     }
 
+    /// Opens `filename` for appending, creating it with mode `0o644` if it
+    /// does not exist.
+    ///
+    /// libssh2 sends every write with an explicit offset, which servers that
+    /// ignore `APPEND` honour, so the file position starts at the current end
+    /// of the file. OpenSSH appends regardless of the offset, which keeps
+    /// writers on several clients from overwriting each other.
+    pub async fn append(&self, filename: &Path) -> Result<File, Error> {
+        let mut file = self
+            .open_mode(
+                filename,
+                OpenFlags::WRITE | OpenFlags::APPEND | OpenFlags::CREATE,
+                0o644,
+                OpenType::File,
+            )
+            .await?;
+        let len = file.stat().await?.len();
+        file.inner.seek(SeekFrom::Start(len))?;
+        Ok(file)
+    }
+
     /// See [`opendir`](ssh2::Sftp::opendir).
     pub async fn opendir(&self, dirname: &Path) -> Result<File, Error> {
         self.open_mode(dirname, OpenFlags::READ, 0, OpenType::Dir)
@@ -65,51 +549,344 @@ impl Sftp {
     }
 
     /// See [`readdir`](ssh2::Sftp::readdir).
-    pub async fn readdir(&self, dirname: &Path) -> Result<Vec<(PathBuf, FileStat)>, Error> {
-        let mut dir = self.opendir(dirname).await?;
+    pub async fn readdir(&self, dirname: &Path) -> Result<Vec<(PathBuf, Metadata)>, Error> {
+        let mut dir = self.read_dir_stream(dirname).await?;
         let mut ret = Vec::new();
-        loop {
-            match dir.readdir().await {
-                Ok((filename, stat)) => {
-                    if &*filename == Path::new(".") || &*filename == Path::new("..") {
-                        continue;
-                    }
+        while let Some(entry) = dir.next_entry().await? {
+            ret.push(entry);
+        }
+        Ok(ret)
+    }
 
-                    ret.push((dirname.join(&filename), stat))
-                }
-                Err(Error::SSH2(ref e)) if e.code() == -16 => {
-                    break;
-                }
-                Err(e) => {
-                    return Err(e);
-                }
+    /// Returns the wrapped [`ssh2::Sftp`]. Available with the `raw-access`
+    /// feature; see [`Session::raw`](crate::Session::raw) for the rules.
+    #[cfg(feature = "raw-access")]
+    pub fn raw(&self) -> &ssh2::Sftp {
+        &self.inner
+    }
+
+    /// Like [`readdir`](Sftp::readdir), but only keeps the entries for which
+    /// `pred` returns true.
+    ///
+    /// `pred` sees each entry as it arrives, so entries it rejects are
+    /// dropped right away instead of being collected first.
+    pub async fn readdir_filtered<F>(
+        &self,
+        dirname: &Path,
+        mut pred: F,
+    ) -> Result<Vec<(PathBuf, Metadata)>, Error>
+    where
+        F: FnMut(&Path, &Metadata) -> bool,
+    {
+        let mut dir = self.read_dir_stream(dirname).await?;
+        let mut ret = Vec::new();
+        while let Some((path, metadata)) = dir.next_entry().await? {
+            if pred(&path, &metadata) {
+                ret.push((path, metadata));
             }
         }
         Ok(ret)
     }
 
+    /// Like [`readdir`](Sftp::readdir), but with the entries sorted by
+    /// `sort`. Ties are broken by name, so the order is stable across calls.
+    pub async fn readdir_sorted(
+        &self,
+        dirname: &Path,
+        sort: SortBy,
+    ) -> Result<Vec<(PathBuf, Metadata)>, Error> {
+        let mut entries = self.readdir(dirname).await?;
+        match sort {
+            SortBy::Name => entries.sort_by(|a, b| a.0.cmp(&b.0)),
+            SortBy::Size => entries.sort_by(|a, b| (a.1.len(), &a.0).cmp(&(b.1.len(), &b.0))),
+            SortBy::Modified => {
+                entries.sort_by(|a, b| (a.1.stat.mtime, &a.0).cmp(&(b.1.stat.mtime, &b.0)))
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Opens `dirname` for reading its entries one at a time.
+    ///
+    /// Unlike [`readdir`](Sftp::readdir), entries are fetched from the
+    /// server as [`ReadDir::next_entry`] is called, or as the [`ReadDir`] is
+    /// polled as a [`Stream`], so huge directories can be processed with
+    /// bounded memory.
+    pub async fn read_dir_stream(&self, dirname: &Path) -> Result<ReadDir, Error> {
+        let dir = self.opendir(dirname).await?;
+        Ok(ReadDir {
+            dirname: dirname.to_owned(),
+            dir: Some(dir),
+            exhausted: false,
+        })
+    }
+
     /// See [`mkdir`](ssh2::Sftp::mkdir).
     pub async fn mkdir(&self, filename: &Path, mode: i32) -> Result<(), Error> {
         let aio = self.aio.clone();
         into_the_future!(aio; &mut || { self.inner.mkdir(filename, mode) })
     }
 
+    /// Creates `path` and any missing parent directories with `mode`.
+    ///
+    /// Directories that already exist, including ones created concurrently by
+    /// another client, are accepted. Other failures are reported as
+    /// [`Error::Path`] naming the directory that could not be created.
+    pub async fn mkdir_all(&self, path: &Path, mode: i32) -> Result<(), Error> {
+        let mut missing = Vec::new();
+        for dir in path.ancestors() {
+            if dir.as_os_str().is_empty() {
+                break;
+            }
+            match self.stat(dir).await {
+                Ok(ref stat) if stat.is_dir() => break,
+                _ => missing.push(dir),
+            }
+        }
+        for dir in missing.into_iter().rev() {
+            self.mkdir_existing_ok(dir, mode).await?;
+        }
+        Ok(())
+    }
+
     /// See [`rmdir`](ssh2::Sftp::rmdir).
     pub async fn rmdir(&self, filename: &Path) -> Result<(), Error> {
         let aio = self.aio.clone();
         into_the_future!(aio; &mut || { self.inner.rmdir(filename) })
     }
 
+    /// Removes the directory `path` together with everything inside it.
+    ///
+    /// Symbolic links are removed themselves rather than followed, including
+    /// when `path` is one. Failures are reported as [`Error::Path`] naming the
+    /// entry that could not be listed or removed.
+    pub async fn remove_dir_all(&self, path: &Path) -> Result<(), Error> {
+        let stat = self.lstat(path).await.map_err(Error::with_path(path))?;
+        if stat.is_symlink() {
+            return self.unlink(path).await.map_err(Error::with_path(path));
+        }
+
+        // `readdir` reports entries without following links, so only real
+        // directories are descended into. They are removed in reverse order
+        // of discovery, after everything beneath them.
+        let mut dirs = vec![path.to_owned()];
+        let mut i = 0;
+        while i < dirs.len() {
+            let entries = self
+                .readdir(&dirs[i])
+                .await
+                .map_err(Error::with_path(&dirs[i]))?;
+            for (entry, stat) in entries {
+                if stat.is_dir() {
+                    dirs.push(entry);
+                } else {
+                    self.unlink(&entry)
+                        .await
+                        .map_err(Error::with_path(&entry))?;
+                }
+            }
+            i += 1;
+        }
+        for dir in dirs.iter().rev() {
+            self.rmdir(dir).await.map_err(Error::with_path(dir))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the paths matching `pattern`, sorted.
+    ///
+    /// Each path component may use `*`, `?` and `[...]` character classes
+    /// (negated with `!` or `^`), which match any file name, dot files
+    /// included. A component that is exactly `**` matches zero or more
+    /// directories; symbolic links are not followed below it, so the walk
+    /// cannot loop. The leading components without wildcards are used as is
+    /// and only the directories below them are listed, so `/var/log/*.gz`
+    /// lists `/var/log` alone. Relative patterns are resolved against the
+    /// sftp working directory.
+    ///
+    /// A directory that cannot be listed fails the whole call with an
+    /// [`Error::Path`] naming it.
+    pub async fn glob(&self, pattern: &str) -> Result<Vec<PathBuf>, Error> {
+        let mut base = PathBuf::new();
+        if pattern.starts_with('/') {
+            base.push("/");
+        }
+        let mut parts = pattern
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .peekable();
+        while let Some(part) = parts.next_if(|part| !is_glob_pattern(part)) {
+            base.push(part);
+        }
+        let parts: Vec<&str> = parts.collect();
+        if parts.is_empty() {
+            let found = !base.as_os_str().is_empty() && self.lstat(&base).await.is_ok();
+            return Ok(if found { vec![base] } else { Vec::new() });
+        }
+
+        let mut matches = Vec::new();
+        let mut pending = vec![(base, 0)];
+        while let Some((dir, i)) = pending.pop() {
+            if i == parts.len() {
+                matches.push(dir);
+                continue;
+            }
+            let listed = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                &dir
+            };
+            let entries = self
+                .readdir(listed)
+                .await
+                .map_err(Error::with_path(listed))?;
+            if parts[i] == "**" {
+                pending.push((dir.clone(), i + 1));
+            }
+            for (entry, stat) in entries {
+                let name = match entry.file_name() {
+                    Some(name) => name,
+                    None => continue,
+                };
+                let path = dir.join(name);
+                if parts[i] == "**" {
+                    if stat.is_dir() {
+                        pending.push((path, i));
+                    }
+                } else if glob_match(parts[i], &name.to_string_lossy()) {
+                    if i + 1 == parts.len() {
+                        matches.push(path);
+                    } else if stat.is_dir()
+                        || (stat.is_symlink()
+                            && self.stat(&path).await.is_ok_and(|stat| stat.is_dir()))
+                    {
+                        pending.push((path, i + 1));
+                    }
+                }
+            }
+        }
+        matches.sort();
+        matches.dedup();
+        Ok(matches)
+    }
+
     /// See [`stat`](ssh2::Sftp::stat).
-    pub async fn stat(&self, filename: &Path) -> Result<ssh2::FileStat, Error> {
+    pub async fn stat(&self, filename: &Path) -> Result<Metadata, Error> {
         let aio = self.aio.clone();
-        into_the_future!(aio; &mut || { self.inner.stat(filename) })
+        into_the_future!(aio; &mut || { self.inner.stat(filename) }).map(Metadata::from)
+    }
+
+    /// Like [`stat`](Sftp::stat), but fails with [`Error::Timeout`] if no
+    /// answer arrives within `timeout`. See [`timeout`](crate::timeout).
+    pub async fn stat_timeout(
+        &self,
+        filename: &Path,
+        timeout: Duration,
+    ) -> Result<Metadata, Error> {
+        crate::timeout(timeout, self.stat(filename)).await
+    }
+
+    /// Returns whether `path` exists, following symbolic links.
+    ///
+    /// libssh2 reports every failure status from the server with the same
+    /// error code, so a missing `path` cannot be told apart from one that
+    /// cannot be looked up, such as an entry in a directory without search
+    /// permission: both count as missing. When `stat` fails but an `lstat`
+    /// succeeds, `path` is a dangling symbolic link, which counts as
+    /// missing, or cannot be stat'ed for another reason, which is returned
+    /// as an error. Transport errors are always returned.
+    pub async fn exists(&self, path: &Path) -> Result<bool, Error> {
+        Ok(self.stat_if_exists(path).await?.is_some())
+    }
+
+    /// Returns whether `path` exists and is a directory, following symbolic
+    /// links. Errors are reported as for [`exists`](Sftp::exists).
+    pub async fn is_dir(&self, path: &Path) -> Result<bool, Error> {
+        Ok(self
+            .stat_if_exists(path)
+            .await?
+            .is_some_and(|stat| stat.is_dir()))
+    }
+
+    /// Returns whether `path` exists and is a regular file, following
+    /// symbolic links. Errors are reported as for [`exists`](Sftp::exists).
+    pub async fn is_file(&self, path: &Path) -> Result<bool, Error> {
+        Ok(self
+            .stat_if_exists(path)
+            .await?
+            .is_some_and(|stat| stat.is_file()))
+    }
+
+    async fn stat_if_exists(&self, path: &Path) -> Result<Option<Metadata>, Error> {
+        let err = match self.stat(path).await {
+            Ok(stat) => return Ok(Some(stat)),
+            Err(Error::SSH2(e)) if e.code() == LIBSSH2_ERROR_SFTP_PROTOCOL => Error::SSH2(e),
+            Err(e) => return Err(e),
+        };
+        match self.lstat(path).await {
+            Ok(stat) if stat.is_symlink() => Ok(None),
+            Ok(_) => Err(err),
+            Err(Error::SSH2(ref e)) if e.code() == LIBSSH2_ERROR_SFTP_PROTOCOL => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns statistics about the filesystem containing `path`, like
+    /// `statvfs(3)`.
+    ///
+    /// libssh2 has `libssh2_sftp_statvfs` for OpenSSH's path based
+    /// `statvfs@openssh.com` extension, but `libssh2-sys` only binds
+    /// `libssh2_sftp_fstatvfs` and `ssh2` does not expose the raw sftp
+    /// session to call it on. The request is therefore sent on a second sftp
+    /// session, as [`hardlink`](Sftp::hardlink) does: every call opens a new
+    /// channel and starts the sftp subsystem on it before the request and
+    /// closes both afterwards, which makes it far more expensive than
+    /// [`File::statvfs`] on an open file. No handle is opened on `path`, so
+    /// it need not be readable. Servers that do not advertise the extension get
+    /// an [`io::ErrorKind::Unsupported`] error; failures from the server are
+    /// reported as [`Error::Path`] naming `path`.
+    pub async fn statvfs(&self, path: &Path) -> Result<StatVfs, Error> {
+        let mut raw = RawSftp::open(&self.session, self.aio.clone()).await?;
+        let res = async {
+            if !raw.supports(STATVFS, b"2") {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "the server does not support statvfs",
+                )
+                .into());
+            }
+            let mut body = Vec::new();
+            raw_sftp::put_string(&mut body, STATVFS);
+            raw_sftp::put_string(&mut body, &raw_sftp::path_bytes(path)?);
+            let reply = raw
+                .extended_request(&body)
+                .await
+                .map_err(Error::with_path(path))?;
+            let mut reply = &reply[..];
+            let mut next = || raw_sftp::get_u64(&mut reply);
+            Ok(StatVfs {
+                bsize: next()?,
+                frsize: next()?,
+                blocks: next()?,
+                bfree: next()?,
+                bavail: next()?,
+                files: next()?,
+                ffree: next()?,
+                favail: next()?,
+                fsid: next()?,
+                flag: next()?,
+                namemax: next()?,
+            })
+        }
+        .await;
+        let closed = raw.close().await;
+        res.and_then(|stat| closed.map(|()| stat))
     }
 
     /// See [`lstat`](ssh2::Sftp::lstat).
-    pub async fn lstat(&self, filename: &Path) -> Result<ssh2::FileStat, Error> {
+    pub async fn lstat(&self, filename: &Path) -> Result<Metadata, Error> {
         let aio = self.aio.clone();
-        into_the_future!(aio; &mut || { self.inner.lstat(filename) })
+        into_the_future!(aio; &mut || { self.inner.lstat(filename) }).map(Metadata::from)
     }
 
     /// See [`setstat`](ssh2::Sftp::setstat).
@@ -118,18 +895,326 @@ impl Sftp {
         into_the_future!(aio; &mut || { self.inner.setstat(filename, stat.clone()) })
     }
 
+    /// Sets the permission bits of `path` to `mode`, leaving its other
+    /// attributes untouched.
+    pub async fn chmod(&self, path: &Path, mode: u32) -> Result<(), Error> {
+        let stat = FileStat {
+            perm: Some(mode & 0o7777),
+            ..UNCHANGED
+        };
+        self.setstat(path, stat).await
+    }
+
+    /// Truncates or extends `path` to `size` bytes, leaving its other
+    /// attributes untouched. Extending fills the file with zeros.
+    ///
+    /// This is a `setstat` that only carries the size, which OpenSSH's sftp
+    /// server applies with `truncate(2)`.
+    pub async fn truncate(&self, path: &Path, size: u64) -> Result<(), Error> {
+        let stat = FileStat {
+            size: Some(size),
+            ..UNCHANGED
+        };
+        self.setstat(path, stat).await
+    }
+
+    /// Changes the owner and/or group of `path`.
+    ///
+    /// The protocol always sets both ids together, so if either is `None` the
+    /// current value is read with [`stat`](Sftp::stat) first.
+    pub async fn chown(
+        &self,
+        path: &Path,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> Result<(), Error> {
+        let (uid, gid) = match (uid, gid) {
+            (None, None) => return Ok(()),
+            (Some(uid), Some(gid)) => (uid, gid),
+            (uid, gid) => {
+                let stat = self.stat(path).await?;
+                (
+                    uid.or(stat.uid()).unwrap_or(0),
+                    gid.or(stat.gid()).unwrap_or(0),
+                )
+            }
+        };
+        let stat = FileStat {
+            uid: Some(uid),
+            gid: Some(gid),
+            ..UNCHANGED
+        };
+        self.setstat(path, stat).await
+    }
+
+    /// Changes the access and/or modification time of `path`, in seconds
+    /// since the Unix epoch.
+    ///
+    /// The protocol always sets both times together, so if either is `None`
+    /// the current value is read with [`stat`](Sftp::stat) first.
+    pub async fn utimes(
+        &self,
+        path: &Path,
+        atime: Option<u64>,
+        mtime: Option<u64>,
+    ) -> Result<(), Error> {
+        let (atime, mtime) = match (atime, mtime) {
+            (None, None) => return Ok(()),
+            (Some(atime), Some(mtime)) => (atime, mtime),
+            (atime, mtime) => {
+                let stat = self.stat(path).await?;
+                (
+                    atime.or(stat.as_file_stat().atime).unwrap_or(0),
+                    mtime.or(stat.as_file_stat().mtime).unwrap_or(0),
+                )
+            }
+        };
+        let stat = FileStat {
+            atime: Some(atime),
+            mtime: Some(mtime),
+            ..UNCHANGED
+        };
+        self.setstat(path, stat).await
+    }
+
     /// See [`symlink`](ssh2::Sftp::symlink).
     pub async fn symlink(&self, path: &Path, target: &Path) -> Result<(), Error> {
         let aio = self.aio.clone();
         into_the_future!(aio; &mut || { self.inner.symlink(path, target) })
     }
 
+    /// Creates `dst` as a hard link to `src`, using OpenSSH's
+    /// `hardlink@openssh.com` extension.
+    ///
+    /// libssh2 cannot send extension requests, so this opens a second sftp
+    /// session on its own channel for the one request, which costs a few
+    /// round trips more than [`symlink`](Sftp::symlink). Servers that do not
+    /// advertise the extension get an [`io::ErrorKind::Unsupported`] error
+    /// without the link being requested; errors from the server are mapped to
+    /// the closest [`io::ErrorKind`], such as `NotFound`, and carry its
+    /// message.
+    pub async fn hardlink(&self, src: &Path, dst: &Path) -> Result<(), Error> {
+        let mut raw = RawSftp::open(&self.session, self.aio.clone()).await?;
+        let res = async {
+            if !raw.supports(HARDLINK, b"1") {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "the server does not support hard links",
+                )
+                .into());
+            }
+            let mut body = Vec::new();
+            raw_sftp::put_string(&mut body, HARDLINK);
+            raw_sftp::put_string(&mut body, &raw_sftp::path_bytes(src)?);
+            raw_sftp::put_string(&mut body, &raw_sftp::path_bytes(dst)?);
+            raw.status_request(raw_sftp::SSH_FXP_EXTENDED, &body).await
+        }
+        .await;
+        let closed = raw.close().await;
+        res.and(closed)
+    }
+
+    /// Copies `src` to `dst` on the server and returns how the data was
+    /// copied. A `dst` that does not exist yet is created with the
+    /// permissions of `src`; an existing one is truncated first.
+    ///
+    /// If the server supports OpenSSH's `copy-data` extension, as OpenSSH
+    /// 9.0 and later do, the server copies the data itself on a second sftp
+    /// session, like [`hardlink`](Sftp::hardlink) uses, and nothing crosses
+    /// the network. Otherwise the file is read and written back through
+    /// this session, which costs its size twice in transfer. No shell is
+    /// run on the server in either case. Failures are reported as
+    /// [`Error::Path`] naming the file involved; `src` and `dst` naming the
+    /// same file, as [`realpath`](Sftp::realpath) resolves them, is an
+    /// [`io::ErrorKind::InvalidInput`] error.
+    pub async fn copy(&self, src: &Path, dst: &Path) -> Result<CopyMethod, Error> {
+        let perm = self
+            .stat(src)
+            .await
+            .map_err(Error::with_path(src))?
+            .permissions()
+            .unwrap_or(0o644);
+        // Opening `dst` truncates it before `src` is read.
+        let real_src = self.realpath(src).await.map_err(Error::with_path(src))?;
+        if self.realpath(dst).await.ok().as_ref() == Some(&real_src) {
+            return Err(Error::with_path(dst)(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "source and destination are the same file",
+            )));
+        }
+
+        let mut raw = RawSftp::open(&self.session, self.aio.clone()).await?;
+        if !raw.supports(COPY_DATA, b"1") {
+            raw.close().await?;
+            self.copy_streamed(src, dst, perm).await?;
+            return Ok(CopyMethod::Streamed);
+        }
+        let res = async {
+            let src_handle = raw
+                .open_handle(src, raw_sftp::SSH_FXF_READ, None)
+                .await
+                .map_err(Error::with_path(src))?;
+            let dst_handle = raw
+                .open_handle(
+                    dst,
+                    raw_sftp::SSH_FXF_WRITE | raw_sftp::SSH_FXF_CREAT | raw_sftp::SSH_FXF_TRUNC,
+                    Some(perm),
+                )
+                .await
+                .map_err(Error::with_path(dst))?;
+            let mut body = Vec::new();
+            raw_sftp::put_string(&mut body, COPY_DATA);
+            raw_sftp::put_string(&mut body, &src_handle);
+            raw_sftp::put_u64(&mut body, 0);
+            // A length of zero copies up to the end of `src`.
+            raw_sftp::put_u64(&mut body, 0);
+            raw_sftp::put_string(&mut body, &dst_handle);
+            raw_sftp::put_u64(&mut body, 0);
+            raw.status_request(raw_sftp::SSH_FXP_EXTENDED, &body)
+                .await
+                .map_err(Error::with_path(dst))?;
+            raw.close_handle(&src_handle)
+                .await
+                .map_err(Error::with_path(src))?;
+            raw.close_handle(&dst_handle)
+                .await
+                .map_err(Error::with_path(dst))
+        }
+        .await;
+        // Closing the channel also closes any handle an error left open.
+        let closed = raw.close().await;
+        res.and(closed).map(|()| CopyMethod::ServerSide)
+    }
+
+    async fn copy_streamed(&self, src: &Path, dst: &Path, perm: u32) -> Result<(), Error> {
+        let mut reader = self.open(src).await.map_err(Error::with_path(src))?;
+        let mut writer = self
+            .open_mode(
+                dst,
+                OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE,
+                perm as i32,
+                OpenType::File,
+            )
+            .await
+            .map_err(Error::with_path(dst))?;
+        let mut buf = vec![0; BUFFER_SIZE];
+        loop {
+            let n = reader.read(&mut buf).await.map_err(Error::with_path(src))?;
+            if n == 0 {
+                break;
+            }
+            writer
+                .write_all(&buf[..n])
+                .await
+                .map_err(Error::with_path(dst))?;
+        }
+        reader.close().await.map_err(Error::with_path(src))?;
+        writer.close().await.map_err(Error::with_path(dst))
+    }
+
+    /// Asks the server to fsync the directory `path`, so that entries
+    /// created, renamed or removed in it survive a crash, using OpenSSH's
+    /// `fsync@openssh.com` extension.
+    ///
+    /// OpenSSH only syncs handles from `open`, not from
+    /// [`opendir`](Sftp::opendir), so the directory is opened like a file
+    /// for reading, on a second sftp session like [`hardlink`](Sftp::hardlink)
+    /// uses. Servers that do not advertise the extension get an
+    /// [`io::ErrorKind::Unsupported`] error rather than a silent success;
+    /// failures from the server are reported as [`Error::Path`] naming
+    /// `path`.
+    pub async fn sync_dir(&self, path: &Path) -> Result<(), Error> {
+        let mut raw = RawSftp::open(&self.session, self.aio.clone()).await?;
+        let res = async {
+            if !raw.supports(FSYNC, b"1") {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "the server does not support fsync",
+                )
+                .into());
+            }
+            let handle = raw
+                .open_handle(path, raw_sftp::SSH_FXF_READ, None)
+                .await
+                .map_err(Error::with_path(path))?;
+            let mut body = Vec::new();
+            raw_sftp::put_string(&mut body, FSYNC);
+            raw_sftp::put_string(&mut body, &handle);
+            raw.status_request(raw_sftp::SSH_FXP_EXTENDED, &body)
+                .await
+                .map_err(Error::with_path(path))?;
+            raw.close_handle(&handle)
+                .await
+                .map_err(Error::with_path(path))
+        }
+        .await;
+        let closed = raw.close().await;
+        res.and(closed)
+    }
+
     /// See [`readlink`](ssh2::Sftp::readlink).
     pub async fn readlink(&self, path: &Path) -> Result<PathBuf, Error> {
         let aio = self.aio.clone();
         into_the_future!(aio; &mut || { self.inner.readlink(path) })
     }
 
+    /// Follows `path` through any chain of symbolic links and returns the
+    /// final target together with its metadata.
+    ///
+    /// Relative link targets are resolved against the directory containing
+    /// the link. A chain that revisits a link, or that is longer than 40
+    /// links, fails with an [`io::ErrorKind::Other`] error naming `path`.
+    /// Other failures, such as a dangling link, are reported as
+    /// [`Error::Path`] naming the link or missing target.
+    pub async fn resolve(&self, path: &Path) -> Result<(PathBuf, Metadata), Error> {
+        let mut current = path.to_owned();
+        let mut seen = HashSet::new();
+        while seen.len() <= MAX_SYMLINKS {
+            let stat = self
+                .lstat(&current)
+                .await
+                .map_err(Error::with_path(&current))?;
+            if !stat.is_symlink() {
+                return Ok((current, stat));
+            }
+            if !seen.insert(current.clone()) {
+                break;
+            }
+            let target = self
+                .readlink(&current)
+                .await
+                .map_err(Error::with_path(&current))?;
+            current = match current.parent() {
+                Some(dir) if target.is_relative() => dir.join(target),
+                _ => target,
+            };
+        }
+        Err(Error::with_path(path)(io::Error::other(
+            "too many levels of symbolic links",
+        )))
+    }
+
+    /// Returns the target of the symbolic link `path` as stored in the link,
+    /// together with the metadata of the file it points to, or `None` if
+    /// the link is dangling.
+    ///
+    /// This takes two requests, a [`readlink`](Sftp::readlink) and a
+    /// [`stat`](Sftp::stat) of `path` itself, which the server resolves
+    /// relative to the link's directory, instead of resolving the target
+    /// on the client. libssh2 does not say why a stat failed, so a target
+    /// that exists but cannot be looked up, for lack of permission, is
+    /// reported as `None` as well.
+    pub async fn lreadlink_stat(&self, path: &Path) -> Result<(PathBuf, Option<Metadata>), Error> {
+        let target = self.readlink(path).await?;
+        match self.stat(path).await {
+            Ok(stat) => Ok((target, Some(stat))),
+            Err(Error::SSH2(ref e)) if e.code() == LIBSSH2_ERROR_SFTP_PROTOCOL => {
+                Ok((target, None))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
     /// See [`realpath`](ssh2::Sftp::realpath).
     pub async fn realpath(&self, path: &Path) -> Result<PathBuf, Error> {
         let aio = self.aio.clone();
@@ -137,6 +1222,9 @@ impl Sftp {
     }
 
     /// See [`rename`](ssh2::Sftp::rename).
+    ///
+    /// `flags` are not sent to the server; see
+    /// [protocol version](Sftp#protocol-version).
     pub async fn rename(
         &self,
         src: &Path,
@@ -147,6 +1235,49 @@ impl Sftp {
         into_the_future!(aio; &mut || { self.inner.rename(src, dst, flags) })
     }
 
+    /// Renames `src` to `dst`, replacing `dst` if it exists.
+    ///
+    /// libssh2 cannot send the `OVERWRITE` flag (see
+    /// [protocol version](Sftp#protocol-version)), and servers such as
+    /// OpenSSH refuse to rename over an existing file. In that
+    /// case `dst` is unlinked and the rename retried, so unlike a single
+    /// rename the replacement is not atomic: `dst` is briefly missing.
+    ///
+    /// libssh2 does not pass on the server's status code, so a refused
+    /// rename is taken to mean that `dst` exists only if the server sent a
+    /// failure status and both `src` and `dst` can be `lstat`ed. Any other
+    /// error, such as a missing `src`, is returned with `dst` untouched.
+    pub async fn rename_overwrite(&self, src: &Path, dst: &Path) -> Result<(), Error> {
+        let flags = RenameFlags::ATOMIC | RenameFlags::OVERWRITE | RenameFlags::NATIVE;
+        let err = match self.rename(src, dst, Some(flags)).await {
+            Err(Error::SSH2(e)) if e.code() == LIBSSH2_ERROR_SFTP_PROTOCOL => Error::SSH2(e),
+            res => return res,
+        };
+        if self.lstat(src).await.is_err() || self.lstat(dst).await.is_err() {
+            return Err(err);
+        }
+        self.unlink(dst).await?;
+        self.rename(src, dst, Some(flags)).await
+    }
+
+    /// Renames `src` to `dst`, failing if `dst` already exists.
+    ///
+    /// Some servers replace the destination whatever the flags say, so `dst`
+    /// is checked first and an existing one is reported as an
+    /// [`io::ErrorKind::AlreadyExists`] error. The check and the rename are
+    /// separate requests, so a `dst` created in between may still be
+    /// replaced on such servers.
+    pub async fn rename_noclobber(&self, src: &Path, dst: &Path) -> Result<(), Error> {
+        if self.lstat(dst).await.is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", dst.display()),
+            )
+            .into());
+        }
+        self.rename(src, dst, Some(RenameFlags::empty())).await
+    }
+
     /// See [`unlink`](ssh2::Sftp::unlink).
     pub async fn unlink(&self, file: &Path) -> Result<(), Error> {
         let aio = self.aio.clone();
@@ -158,114 +1289,1383 @@ impl Sftp {
         let aio = self.aio.clone();
         into_the_future!(aio; &mut || { self.inner.shutdown() })
     }
-}
 
-impl File {
-    pub(crate) fn new(file: ssh2::File, aio: Arc<Option<Aio>>) -> Self {
-        Self { inner: file, aio }
+    /// Reads the entire contents of the remote file `path`.
+    pub async fn read(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        let mut file = self.open(path).await?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).await?;
+        file.close().await?;
+        Ok(data)
     }
 
-    /// See [`setstat`](ssh2::File::setstat).
-    pub async fn setstat(&mut self, stat: FileStat) -> Result<(), Error> {
-        let aio = self.aio.clone();
-        into_the_future!(aio; &mut || { self.inner.setstat(stat.clone()) })
+    /// Reads the entire contents of the remote file `path` as text.
+    ///
+    /// Contents that are not valid UTF-8 are reported as an [`Error::Path`]
+    /// naming `path` and wrapping an [`Error::Utf8`], which still holds the
+    /// bytes.
+    pub async fn read_to_string(&self, path: &Path) -> Result<String, Error> {
+        let data = self.read(path).await?;
+        String::from_utf8(data).map_err(Error::with_path(path))
     }
 
-    /// See [`stat`](ssh2::File::stat).
-    pub async fn stat(&mut self) -> Result<FileStat, Error> {
-        let aio = self.aio.clone();
-        into_the_future!(aio; &mut || { self.inner.stat() })
+    /// Creates or truncates the remote file `path` and writes `data` to it.
+    pub async fn write(&self, path: &Path, data: &[u8]) -> Result<(), Error> {
+        let mut file = self.create(path).await?;
+        file.write_all(data).await?;
+        file.flush().await?;
+        file.close().await
     }
 
-    #[allow(missing_docs)]
-    /// See [`statvfs`](ssh2::File::statvfs).
-    // TODO
-    /*
-    pub async fn statvfs(&mut self) -> Result<raw::LIBSSH2_SFTP_STATVFS, Error> {
-        let aio = self.aio.clone();
-        into_the_future!(aio; &mut || { self.inner.statvfs() })
+    /// Writes `data` to `dst` so that readers see either the old contents or
+    /// all of `data`, never a partially written file.
+    ///
+    /// The data is written and fsynced to a temporary file next to `dst`,
+    /// which is then renamed over `dst`. Servers that only speak sftp v3, such
+    /// as OpenSSH, refuse to rename over an existing file; in that case `dst`
+    /// is unlinked first, so it is briefly missing but never truncated. The
+    /// temporary file is removed if any step fails.
+    pub async fn upload_atomic(&self, data: &[u8], dst: &Path) -> Result<(), Error> {
+        let tmp = temp_sibling(dst);
+        let res = self.upload_atomic_via(data, &tmp, dst).await;
+        if res.is_err() {
+            let _ = self.unlink(&tmp).await;
+        }
+        res
     }
-    */
 
-    /// See [`readdir`](ssh2::File::readdir).
-    pub async fn readdir(&mut self) -> Result<(PathBuf, FileStat), Error> {
-        let aio = self.aio.clone();
-        into_the_future!(aio; &mut || { self.inner.readdir() })
-    }
+    async fn upload_atomic_via(&self, data: &[u8], tmp: &Path, dst: &Path) -> Result<(), Error> {
+        let mut file = self
+            .open_mode(
+                tmp,
+                OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::EXCLUSIVE,
+                0o644,
+                OpenType::File,
+            )
+            .await
+            .map_err(Error::with_path(tmp))?;
+        file.write_all(data).await.map_err(Error::with_path(tmp))?;
+        file.flush().await.map_err(Error::with_path(tmp))?;
+        file.fsync().await.map_err(Error::with_path(tmp))?;
+        file.close().await.map_err(Error::with_path(tmp))?;
 
-    /// See [`fsync`](ssh2::File::fsync).
-    pub async fn fsync(&mut self) -> Result<(), Error> {
-        let aio = self.aio.clone();
-        into_the_future!(aio; &mut || { self.inner.fsync() })
+        self.rename_overwrite(tmp, dst)
+            .await
+            .map_err(Error::with_path(dst))
     }
 
-    /// See [`close`](ssh2::File::close).
-    pub async fn close(mut self) -> Result<(), Error> {
-        let aio = self.aio.clone();
-        into_the_future!(aio; &mut || { self.inner.close() })
+    /// Streams the remote file `src` into `dst` on `dst_sftp`, which may
+    /// belong to another session, and returns the number of bytes copied.
+    ///
+    /// Data passes through a 32 KiB buffer and is never held in full locally.
+    /// The first failure on either side aborts the copy and is reported as
+    /// [`Error::Path`] naming `src` or `dst`; `dst` is left partially written
+    /// in that case.
+    pub async fn copy_to(&self, src: &Path, dst_sftp: &Sftp, dst: &Path) -> Result<u64, Error> {
+        let mut reader = self.open(src).await.map_err(Error::with_path(src))?;
+        let mut writer = dst_sftp.create(dst).await.map_err(Error::with_path(dst))?;
+        let mut buf = vec![0; BUFFER_SIZE];
+        let mut copied = 0;
+        loop {
+            let n = reader.read(&mut buf).await.map_err(Error::with_path(src))?;
+            if n == 0 {
+                break;
+            }
+            writer
+                .write_all(&buf[..n])
+                .await
+                .map_err(Error::with_path(dst))?;
+            copied += n as u64;
+        }
+        reader.close().await.map_err(Error::with_path(src))?;
+        writer.close().await.map_err(Error::with_path(dst))?;
+        Ok(copied)
     }
-}
 
-impl AsyncRead for File {
-    fn poll_read(
-        mut self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &mut [u8],
-    ) -> Poll<io::Result<usize>> {
-        loop {
-            let res = self.inner.read(buf);
-            match res {
-                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    if let Some(ref aio) = *self.aio {
-                        aio.set_waker(cx)?;
+    /// Uploads the local file `local` to `remote`, then reads `remote` back
+    /// and compares it with `local`, returning the number of bytes uploaded.
+    ///
+    /// sftp has no checksum request, so the comparison streams both files
+    /// through a 32 KiB buffer instead of hashing them. There is no option
+    /// to hash with a remote command such as `sha256sum` instead: this crate
+    /// has no hash implementation to compute the local digest with, and
+    /// sftp-only accounts (`ForceCommand internal-sftp`) cannot exec
+    /// commands at all. The read-back costs a full download of `remote`, so
+    /// the call moves twice the file size over the connection and takes
+    /// about twice as long as the upload alone; for large files on
+    /// servers with a shell, running a hash command through
+    /// [`Session::channel_session`](crate::Session::channel_session) and
+    /// comparing it with a local digest is cheaper.
+    ///
+    /// A mismatch is reported as an [`io::ErrorKind::InvalidData`]
+    /// error; like any other failure it comes back as [`Error::Path`] naming
+    /// the file involved.
+    pub async fn upload_verified(&self, local: &Path, remote: &Path) -> Result<u64, Error> {
+        let metadata = fs::metadata(local).await.map_err(Error::with_path(local))?;
+        self.upload_file(local, remote, &metadata, &mut |_: &Path, _, _| {})
+            .await?;
+
+        let mut expected = fs::File::open(local)
+            .await
+            .map_err(Error::with_path(local))?;
+        let mut actual = self.open(remote).await.map_err(Error::with_path(remote))?;
+        // Close the read-back handle on every path, mismatches included.
+        let res = async {
+            let mut buf = vec![0; BUFFER_SIZE];
+            let mut local_buf = vec![0; BUFFER_SIZE];
+            let mut len = 0;
+            let mismatch = || {
+                Error::with_path(remote)(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "uploaded file does not match the local file",
+                ))
+            };
+            loop {
+                let n = actual
+                    .read(&mut buf)
+                    .await
+                    .map_err(Error::with_path(remote))?;
+                if n == 0 {
+                    break;
+                }
+                match expected.read_exact(&mut local_buf[..n]).await {
+                    Ok(_) if buf[..n] == local_buf[..n] => len += n as u64,
+                    Ok(_) => return Err(mismatch()),
+                    Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                        return Err(mismatch())
                     }
-                    return Poll::Pending;
+                    Err(e) => return Err(Error::with_path(local)(e)),
                 }
-                Err(e) => return Poll::Ready(Err(e)),
-                Ok(val) => return Poll::Ready(Ok(val)),
             }
+            if expected
+                .read(&mut local_buf[..1])
+                .await
+                .map_err(Error::with_path(local))?
+                != 0
+            {
+                return Err(mismatch());
+            }
+            Ok(len)
         }
+        .await;
+        let closed = actual.close().await.map_err(Error::with_path(remote));
+        res.and_then(|len| closed.map(|()| len))
     }
-}
 
-impl AsyncWrite for File {
-    fn poll_write(
-        mut self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &[u8],
-    ) -> Poll<Result<usize, io::Error>> {
+    /// Recursively uploads the local directory `local` to `remote`.
+    ///
+    /// Remote directories are created as needed and file modes are carried
+    /// over where the local platform exposes them. Symbolic links are followed
+    /// if `follow_symlinks` is set and skipped otherwise. When following them,
+    /// a link back to a directory that is already being uploaded above it is
+    /// skipped rather than recursed into forever; this check needs device and
+    /// inode numbers and only happens on Unix. `progress` is called
+    /// with the local path, the number of bytes sent so far and the total size
+    /// of each file as it is transferred.
+    ///
+    /// Failures are reported as [`Error::Path`] naming the local or remote
+    /// path that could not be processed.
+    pub async fn upload_dir<F>(
+        &self,
+        local: &Path,
+        remote: &Path,
+        follow_symlinks: bool,
+        mut progress: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(&Path, u64, u64),
+    {
+        // Each directory carries the ids of its ancestors to detect cycles.
+        let mut dirs = vec![(local.to_owned(), remote.to_owned(), Vec::new())];
+        while let Some((local, remote, mut ancestors)) = dirs.pop() {
+            let metadata = fs::metadata(&local)
+                .await
+                .map_err(Error::with_path(&local))?;
+            if let Some(id) = local_dir_id(&metadata) {
+                if ancestors.contains(&id) {
+                    continue;
+                }
+                ancestors.push(id);
+            }
+            self.mkdir_existing_ok(&remote, local_mode(&metadata))
+                .await?;
+
+            let mut entries = fs::read_dir(&local)
+                .await
+                .map_err(Error::with_path(&local))?;
+            while let Some(entry) = entries
+                .next_entry()
+                .await
+                .map_err(Error::with_path(&local))?
+            {
+                let local = entry.path();
+                let remote = remote.join(entry.file_name());
+                let metadata = if follow_symlinks {
+                    fs::metadata(&local).await
+                } else {
+                    fs::symlink_metadata(&local).await
+                }
+                .map_err(Error::with_path(&local))?;
+
+                if metadata.is_dir() {
+                    dirs.push((local, remote, ancestors.clone()));
+                } else if metadata.is_file() {
+                    self.upload_file(&local, &remote, &metadata, &mut progress)
+                        .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Like [`upload_dir`](Sftp::upload_dir), but reports progress through
+    /// the returned [`ProgressReceiver`] instead of a callback.
+    ///
+    /// Nothing happens until the returned future is polled, and the
+    /// receiver only yields updates while it is, so drive both together,
+    /// for example with `tokio::join!`. The receiver ends once the future
+    /// has completed or been dropped.
+    pub fn upload_dir_progress<'a>(
+        &'a self,
+        local: &'a Path,
+        remote: &'a Path,
+        follow_symlinks: bool,
+    ) -> (
+        impl Future<Output = Result<(), Error>> + 'a,
+        ProgressReceiver,
+    ) {
+        let (sender, receiver) = progress::channel();
+        let upload = async move {
+            self.upload_dir(local, remote, follow_symlinks, |path, done, total| {
+                sender.send(path, done, total)
+            })
+            .await
+        };
+        (upload, receiver)
+    }
+
+    async fn upload_file<F>(
+        &self,
+        local: &Path,
+        remote: &Path,
+        metadata: &std::fs::Metadata,
+        progress: &mut F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(&Path, u64, u64),
+    {
+        let mut src = fs::File::open(local)
+            .await
+            .map_err(Error::with_path(local))?;
+        let mut dst = self
+            .create(remote)
+            .await
+            .map_err(Error::with_path(remote))?;
+
+        let total = metadata.len();
+        let mut sent = 0;
+        let mut buf = vec![0; BUFFER_SIZE];
+        progress(local, sent, total);
         loop {
-            let res = self.inner.write(buf);
-            match res {
-                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    if let Some(ref aio) = *self.aio {
-                        aio.set_waker(cx)?;
+            let n = src.read(&mut buf).await.map_err(Error::with_path(local))?;
+            if n == 0 {
+                break;
+            }
+            dst.write_all(&buf[..n])
+                .await
+                .map_err(Error::with_path(remote))?;
+            sent += n as u64;
+            progress(local, sent, total);
+        }
+
+        let stat = FileStat {
+            perm: Some(local_mode(metadata) as u32),
+            ..UNCHANGED
+        };
+        dst.setstat(stat).await.map_err(Error::with_path(remote))?;
+        dst.close().await.map_err(Error::with_path(remote))
+    }
+
+    /// Recursively downloads the remote directory `remote` to `local`.
+    ///
+    /// Files that already exist locally are replaced if `overwrite` is set and
+    /// left untouched otherwise. Entries that are neither regular files nor
+    /// directories, such as symbolic links, are skipped.
+    ///
+    /// If `fail_fast` is set the first failure aborts the transfer. Otherwise
+    /// entries that fail are skipped and their errors returned once the rest
+    /// of the tree has been copied. Failing to list `remote` itself is always
+    /// fatal. Failures are reported as [`Error::Path`].
+    pub async fn download_dir(
+        &self,
+        remote: &Path,
+        local: &Path,
+        overwrite: bool,
+        fail_fast: bool,
+    ) -> Result<Vec<Error>, Error> {
+        let mut errors = Vec::new();
+        let mut dirs = vec![(remote.to_owned(), local.to_owned())];
+        while let Some((remote_dir, local_dir)) = dirs.pop() {
+            let entries = match self.prepare_download_dir(&remote_dir, &local_dir).await {
+                Ok(entries) => entries,
+                Err(e) if fail_fast || remote_dir == remote => return Err(e),
+                Err(e) => {
+                    errors.push(e);
+                    continue;
+                }
+            };
+
+            for (remote, stat) in entries {
+                let local = match remote.file_name() {
+                    Some(name) => local_dir.join(name),
+                    None => continue,
+                };
+                if stat.is_dir() {
+                    dirs.push((remote, local));
+                } else if stat.is_file() {
+                    if let Err(e) = self.download_file(&remote, &local, &stat, overwrite).await {
+                        if fail_fast {
+                            return Err(e);
+                        }
+                        errors.push(e);
                     }
+                }
+            }
+        }
+        Ok(errors)
+    }
+
+    async fn prepare_download_dir(
+        &self,
+        remote: &Path,
+        local: &Path,
+    ) -> Result<Vec<(PathBuf, Metadata)>, Error> {
+        if let Err(e) = fs::create_dir(local).await {
+            let is_dir = fs::metadata(local).await.map(|m| m.is_dir());
+            if e.kind() != io::ErrorKind::AlreadyExists || !is_dir.unwrap_or(false) {
+                return Err(Error::with_path(local)(e));
+            }
+        }
+        self.readdir(remote).await.map_err(Error::with_path(remote))
+    }
+
+    async fn download_file(
+        &self,
+        remote: &Path,
+        local: &Path,
+        stat: &Metadata,
+        overwrite: bool,
+    ) -> Result<(), Error> {
+        let mut options = fs::OpenOptions::new();
+        if overwrite {
+            options.write(true).create(true).truncate(true);
+        } else {
+            options.write(true).create_new(true);
+        }
+        let mut dst = match options.open(local).await {
+            Ok(dst) => dst,
+            Err(ref e) if !overwrite && e.kind() == io::ErrorKind::AlreadyExists => return Ok(()),
+            Err(e) => return Err(Error::with_path(local)(e)),
+        };
+        let mut src = self.open(remote).await.map_err(Error::with_path(remote))?;
+
+        let mut buf = vec![0; BUFFER_SIZE];
+        loop {
+            let n = src.read(&mut buf).await.map_err(Error::with_path(remote))?;
+            if n == 0 {
+                break;
+            }
+            dst.write_all(&buf[..n])
+                .await
+                .map_err(Error::with_path(local))?;
+        }
+        dst.flush().await.map_err(Error::with_path(local))?;
+        src.close().await.map_err(Error::with_path(remote))?;
+
+        if let Some(perm) = stat.permissions() {
+            set_local_mode(local, perm).await?;
+        }
+        Ok(())
+    }
+
+    /// Downloads each `(remote, local)` pair, keeping up to `concurrency`
+    /// files open at once.
+    ///
+    /// A session can only run one libssh2 call at a time, so the open files
+    /// are read in turn rather than from separate tasks. Each read leaves
+    /// read-ahead requests outstanding, so the transfers still overlap on the
+    /// network. Local files are created or truncated. The results are in the
+    /// order of `pairs`, with failures reported as [`Error::Path`].
+    pub async fn download_many<I>(&self, pairs: I, concurrency: usize) -> Vec<Result<(), Error>>
+    where
+        I: IntoIterator<Item = (PathBuf, PathBuf)>,
+    {
+        let mut pairs = pairs.into_iter();
+        let mut results = Vec::new();
+        let mut active = Vec::new();
+        let mut buf = vec![0; BUFFER_SIZE];
+        loop {
+            while active.len() < cmp::max(concurrency, 1) {
+                let (remote, local) = match pairs.next() {
+                    Some(pair) => pair,
+                    None => break,
+                };
+                results.push(Ok(()));
+                match self.start_download(results.len() - 1, remote, local).await {
+                    Ok(download) => active.push(download),
+                    Err(e) => *results.last_mut().unwrap() = Err(e),
+                }
+            }
+            if active.is_empty() {
+                return results;
+            }
+
+            let mut i = 0;
+            while i < active.len() {
+                match active[i].step(&mut buf).await {
+                    Ok(true) => i += 1,
+                    Ok(false) => {
+                        let download = active.remove(i);
+                        let index = download.index;
+                        results[index] = download.finish().await;
+                    }
+                    Err(e) => results[active.remove(i).index] = Err(e),
+                }
+            }
+        }
+    }
+
+    async fn start_download(
+        &self,
+        index: usize,
+        remote: PathBuf,
+        local: PathBuf,
+    ) -> Result<Download, Error> {
+        let src = self
+            .open(&remote)
+            .await
+            .map_err(Error::with_path(&remote))?;
+        let dst = fs::File::create(&local)
+            .await
+            .map_err(Error::with_path(&local))?;
+        Ok(Download {
+            index,
+            remote,
+            local,
+            src,
+            dst,
+        })
+    }
+
+    /// Creates `path`, treating an already existing directory as success.
+    async fn mkdir_existing_ok(&self, path: &Path, mode: i32) -> Result<(), Error> {
+        match self.mkdir(path, mode).await {
+            Ok(()) => Ok(()),
+            Err(e) => match self.stat(path).await {
+                Ok(ref stat) if stat.is_dir() => Ok(()),
+                _ => Err(Error::with_path(path)(e)),
+            },
+        }
+    }
+}
+
+impl Drop for Sftp {
+    fn drop(&mut self) {
+        // Safe because `inner` is not used again.
+        let inner = unsafe { ManuallyDrop::take(&mut self.inner) };
+        match &*self.aio {
+            Some(aio) => aio.defer_close(Deferred::Sftp(inner)),
+            None => mem::forget(inner),
+        }
+    }
+}
+
+/// A file being copied by [`Sftp::download_many`].
+struct Download {
+    index: usize,
+    remote: PathBuf,
+    local: PathBuf,
+    src: File,
+    dst: fs::File,
+}
+
+impl Download {
+    /// Copies one chunk, returning `false` once the remote file is exhausted.
+    async fn step(&mut self, buf: &mut [u8]) -> Result<bool, Error> {
+        let n = self
+            .src
+            .read(buf)
+            .await
+            .map_err(Error::with_path(&self.remote))?;
+        self.dst
+            .write_all(&buf[..n])
+            .await
+            .map_err(Error::with_path(&self.local))?;
+        Ok(n > 0)
+    }
+
+    async fn finish(mut self) -> Result<(), Error> {
+        self.dst
+            .flush()
+            .await
+            .map_err(Error::with_path(&self.local))?;
+        self.src
+            .close()
+            .await
+            .map_err(Error::with_path(&self.remote))
+    }
+}
+
+/// Returns a path next to `path` that is unlikely to exist, for example
+/// `.config.toml.4242-1-1a2b3c.tmp` for `config.toml`.
+fn temp_sibling(path: &Path) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    path.with_file_name(format!(
+        ".{}.{}-{}-{:x}.tmp",
+        name,
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+        nanos
+    ))
+}
+
+fn is_glob_pattern(part: &str) -> bool {
+    part.contains(['*', '?', '['])
+}
+
+/// Matches a single file name against a glob component.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where to resume after the last `*` if the rest fails to match.
+    let mut star = None;
+    while n < name.len() {
+        if p < pattern.len() {
+            match pattern[p] {
+                '*' => {
+                    star = Some((p + 1, n));
+                    p += 1;
+                    continue;
+                }
+                '?' => {
+                    p += 1;
+                    n += 1;
+                    continue;
+                }
+                '[' => {
+                    if let Some((matched, next)) = match_class(&pattern[p + 1..], name[n]) {
+                        if matched {
+                            p += 1 + next;
+                            n += 1;
+                            continue;
+                        }
+                    } else if name[n] == '[' {
+                        p += 1;
+                        n += 1;
+                        continue;
+                    }
+                }
+                c if c == name[n] => {
+                    p += 1;
+                    n += 1;
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        match star {
+            Some((sp, sn)) => {
+                p = sp;
+                n = sn + 1;
+                star = Some((sp, sn + 1));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Matches `c` against the character class starting just after a `[`.
+/// Returns whether it matched and the length of the class including the
+/// closing `]`, or `None` if the class is not terminated.
+fn match_class(class: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 0;
+    let negated = matches!(class.first(), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    while i < class.len() {
+        if class[i] == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        if i + 2 < class.len() && class[i + 1] == '-' && class[i + 2] != ']' {
+            matched |= class[i] <= c && c <= class[i + 2];
+            i += 3;
+        } else {
+            matched |= class[i] == c;
+            i += 1;
+        }
+    }
+    None
+}
+
+#[cfg(unix)]
+fn local_mode(metadata: &std::fs::Metadata) -> i32 {
+    use std::os::unix::fs::PermissionsExt;
+    (metadata.permissions().mode() & 0o7777) as i32
+}
+
+#[cfg(unix)]
+fn local_dir_id(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(unix)]
+async fn set_local_mode(path: &Path, mode: u32) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    let permissions = std::fs::Permissions::from_mode(mode & 0o7777);
+    fs::set_permissions(path, permissions)
+        .await
+        .map_err(Error::with_path(path))
+}
+
+#[cfg(not(unix))]
+async fn set_local_mode(_path: &Path, _mode: u32) -> Result<(), Error> {
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn local_dir_id(_metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(not(unix))]
+fn local_mode(metadata: &std::fs::Metadata) -> i32 {
+    if metadata.is_dir() {
+        0o755
+    } else if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+impl File {
+    pub(crate) fn new(file: ssh2::File, aio: Arc<Option<Aio>>) -> Self {
+        Self {
+            inner: ManuallyDrop::new(file),
+            aio,
+            closed: false,
+            close_failed: false,
+            seek: None,
+            read_timeout: ReadTimeout::default(),
+            read_chunk_size: None,
+            rate_limit: RateLimit::default(),
+            sync_on_flush: false,
+            unsynced: false,
+            transferred: 0,
+            buf: Vec::new(),
+            pos: 0,
+            filled: 0,
+        }
+    }
+
+    /// Makes reads through `AsyncRead` and `AsyncBufRead` fail with
+    /// [`io::ErrorKind::TimedOut`] once no data has arrived for `timeout`.
+    /// `None`, the default, waits forever.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout.set(timeout)
+    }
+
+    /// Returns the timeout set with [`set_read_timeout`](File::set_read_timeout).
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout.get()
+    }
+
+    /// Returns the wrapped [`ssh2::File`]. Available with the `raw-access`
+    /// feature; see [`Session::raw`](crate::Session::raw) for the rules.
+    #[cfg(feature = "raw-access")]
+    pub fn raw(&self) -> &ssh2::File {
+        &self.inner
+    }
+
+    /// Like [`raw`](File::raw), but mutable, as most of [`ssh2::File`]'s
+    /// methods need.
+    ///
+    /// Reads through it skip data already read ahead by `AsyncBufRead` and
+    /// any seek that has been started but not completed. The handle must
+    /// not be closed through it; use [`close`](File::close) instead.
+    #[cfg(feature = "raw-access")]
+    pub fn raw_mut(&mut self) -> &mut ssh2::File {
+        &mut self.inner
+    }
+
+    /// Caps each read through `AsyncRead` and `AsyncBufRead`, and through a
+    /// [`BufferedReader`] on top of this file, at `n` bytes, however large
+    /// the buffer passed in. A size of 0 removes the cap, which is the
+    /// default.
+    ///
+    /// libssh2 splits each read into requests of at most 30000 bytes and
+    /// asks for up to four times the read size ahead of what has been
+    /// returned, buffering the answers until they are read. Capping the read
+    /// size therefore bounds that buffer to roughly `4 * n` bytes, at the
+    /// cost of throughput once `n` falls well below the bandwidth-delay
+    /// product of the link.
+    pub fn set_read_chunk_size(&mut self, n: usize) {
+        self.read_chunk_size = if n == 0 { None } else { Some(n) };
+    }
+
+    /// Returns the cap set with
+    /// [`set_read_chunk_size`](File::set_read_chunk_size), if any.
+    pub fn read_chunk_size(&self) -> Option<usize> {
+        self.read_chunk_size
+    }
+
+    /// Limits reads and writes through `AsyncRead`, `AsyncBufRead` and
+    /// `AsyncWrite` to `bytes_per_sec` bytes per second, counting both
+    /// directions together; `None`, the default, does not limit them.
+    ///
+    /// Transfers are delayed with a timer, and up to a second's worth of
+    /// bytes may go out in a burst after the file has been idle. libssh2
+    /// requests data ahead of what has been read, so the rate on the wire
+    /// can briefly exceed the limit when reading starts; see
+    /// [`set_read_chunk_size`](File::set_read_chunk_size).
+    pub fn set_rate_limit(&mut self, bytes_per_sec: Option<u64>) {
+        self.rate_limit.set(bytes_per_sec)
+    }
+
+    /// Returns the limit set with [`set_rate_limit`](File::set_rate_limit).
+    pub fn rate_limit(&self) -> Option<u64> {
+        self.rate_limit.get()
+    }
+
+    fn read_len(&self, len: usize) -> usize {
+        self.read_chunk_size.map_or(len, |n| cmp::min(n, len))
+    }
+
+    /// Makes `AsyncWrite::poll_flush`, and with it `poll_shutdown`, ask the
+    /// server to [`fsync`](File::fsync) the file if anything was written
+    /// since the last flush. Off by default.
+    ///
+    /// This needs the `fsync@openssh.com` extension; flushing fails on
+    /// servers that do not support it.
+    pub fn set_sync_on_flush(&mut self, sync: bool) {
+        self.sync_on_flush = sync;
+    }
+
+    /// Returns whether [`set_sync_on_flush`](File::set_sync_on_flush) is
+    /// enabled.
+    pub fn sync_on_flush(&self) -> bool {
+        self.sync_on_flush
+    }
+
+    /// See [`setstat`](ssh2::File::setstat).
+    pub async fn setstat(&mut self, stat: FileStat) -> Result<(), Error> {
+        let aio = self.aio.clone();
+        into_the_future!(aio; &mut || { self.inner.setstat(stat.clone()) })
+    }
+
+    /// Truncates or extends the file to `size` bytes, like
+    /// [`Sftp::truncate`]. The position is left where it was, so writing
+    /// past the new end leaves a hole filled with zeros.
+    pub async fn set_len(&mut self, size: u64) -> Result<(), Error> {
+        self.discard_buffer()?;
+        let stat = FileStat {
+            size: Some(size),
+            ..UNCHANGED
+        };
+        self.setstat(stat).await
+    }
+
+    /// See [`stat`](ssh2::File::stat).
+    pub async fn stat(&mut self) -> Result<Metadata, Error> {
+        let aio = self.aio.clone();
+        into_the_future!(aio; &mut || { self.inner.stat() }).map(Metadata::from)
+    }
+
+    /// See [`statvfs`](ssh2::File::statvfs).
+    pub async fn statvfs(&mut self) -> Result<StatVfs, Error> {
+        let aio = self.aio.clone();
+        let raw = into_the_future!(aio; &mut || { self.inner.statvfs() })?;
+        Ok(StatVfs {
+            bsize: raw.f_bsize,
+            frsize: raw.f_frsize,
+            blocks: raw.f_blocks,
+            bfree: raw.f_bfree,
+            bavail: raw.f_bavail,
+            files: raw.f_files,
+            ffree: raw.f_ffree,
+            favail: raw.f_favail,
+            fsid: raw.f_fsid,
+            flag: raw.f_flag,
+            namemax: raw.f_namemax,
+        })
+    }
+
+    /// See [`readdir`](ssh2::File::readdir).
+    ///
+    /// The end of the directory is reported as an [`Error::SSH2`] with code
+    /// `LIBSSH2_ERROR_FILE` (-16), while errors from the server carry
+    /// `LIBSSH2_ERROR_SFTP_PROTOCOL` (-31).
+    pub async fn readdir(&mut self) -> Result<(PathBuf, Metadata), Error> {
+        let aio = self.aio.clone();
+        into_the_future!(aio; &mut || { self.inner.readdir() })
+            .map(|(path, stat)| (path, Metadata::from(stat)))
+    }
+
+    /// See [`fsync`](ssh2::File::fsync).
+    pub async fn fsync(&mut self) -> Result<(), Error> {
+        let aio = self.aio.clone();
+        into_the_future!(aio; &mut || { self.inner.fsync() })
+    }
+
+    /// Reads into `buf` starting `offset` bytes from the beginning of the
+    /// file, regardless of the current position.
+    ///
+    /// The position is left just past the bytes read, and any seek that has
+    /// been started but not completed is discarded.
+    pub async fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        self.seek_to(offset)?;
+        Ok(self.read(buf).await?)
+    }
+
+    /// Writes `buf` starting `offset` bytes from the beginning of the file,
+    /// regardless of the current position.
+    ///
+    /// The position is left just past the bytes written, and any seek that
+    /// has been started but not completed is discarded.
+    ///
+    /// Like [`AsyncWrite::poll_write`], this may write only part of `buf`; see
+    /// [`write_all_at`](File::write_all_at).
+    pub async fn write_at(&mut self, offset: u64, buf: &[u8]) -> Result<usize, Error> {
+        self.seek_to(offset)?;
+        Ok(self.write(buf).await?)
+    }
+
+    /// Writes all of `buf` starting `offset` bytes from the beginning of the
+    /// file, retrying short writes until every byte has been acknowledged.
+    ///
+    /// The position is left just past `buf`. A write that makes no progress
+    /// fails with [`io::ErrorKind::WriteZero`]. To write everything at the
+    /// current position, use `AsyncWriteExt::write_all`, which loops the same
+    /// way.
+    pub async fn write_all_at(&mut self, offset: u64, buf: &[u8]) -> Result<(), Error> {
+        self.seek_to(offset)?;
+        Ok(self.write_all(buf).await?)
+    }
+
+    /// Moves the position to `offset`, discarding any read-ahead data and
+    /// any seek that has been started but not completed.
+    fn seek_to(&mut self, offset: u64) -> io::Result<()> {
+        self.seek = None;
+        self.discard_buffer()?;
+        self.inner.seek(SeekFrom::Start(offset))?;
+        Ok(())
+    }
+
+    /// Returns the number of bytes read from and written to the file through
+    /// this handle so far.
+    ///
+    /// Bytes read ahead by [`AsyncBufRead`] are counted once they are
+    /// consumed.
+    pub fn bytes_transferred(&self) -> u64 {
+        self.transferred
+    }
+
+    /// Drops the data read ahead by [`AsyncBufRead`] and moves the libssh2
+    /// file pointer back to the first byte that has not been consumed.
+    fn discard_buffer(&mut self) -> io::Result<()> {
+        let unread = self.filled - self.pos;
+        self.pos = 0;
+        self.filled = 0;
+        if unread > 0 {
+            self.inner.seek(SeekFrom::Current(-(unread as i64)))?;
+        }
+        Ok(())
+    }
+
+    /// See [`close`](ssh2::File::close).
+    ///
+    /// Closing a file that was already shut down through `AsyncWrite` is a
+    /// no-op.
+    pub async fn close(mut self) -> Result<(), Error> {
+        if self.closed {
+            return Ok(());
+        }
+        let aio = self.aio.clone();
+        let res = into_the_future!(aio; &mut || { self.inner.close() });
+        self.closed = true;
+        self.close_failed = res.is_err();
+        res
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        // Safe because `inner` is not used again.
+        let inner = unsafe { ManuallyDrop::take(&mut self.inner) };
+        match &*self.aio {
+            _ if self.close_failed => mem::forget(inner),
+            _ if self.closed => drop(inner),
+            Some(aio) => aio.defer_close(Deferred::File(inner)),
+            None => mem::forget(inner),
+        }
+    }
+}
+
+impl AsyncRead for File {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.seek.is_some() && self.as_mut().poll_complete(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        // libssh2 fails an empty read with an internal error. Zero bytes
+        // from it otherwise always mean the end of the file.
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        if self.pos < self.filled {
+            let n = cmp::min(self.filled - self.pos, buf.len());
+            buf[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+            self.pos += n;
+            self.transferred += n as u64;
+            return Poll::Ready(Ok(n));
+        }
+        let len = self.read_len(buf.len());
+        let len = match self.rate_limit.poll_acquire(cx, len) {
+            Poll::Ready(len) => len,
+            Poll::Pending => return Poll::Pending,
+        };
+        let res = self.inner.read(&mut buf[..len]);
+        match res {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.read_timeout.poll_blocked(cx)?;
+                aio::set_waker(&self.aio, cx)?;
+                Poll::Pending
+            }
+            Err(e) => Poll::Ready(Err(e)),
+            Ok(val) => {
+                self.read_timeout.reset();
+                self.rate_limit.consume(val);
+                self.transferred += val as u64;
+                Poll::Ready(Ok(val))
+            }
+        }
+    }
+}
+
+impl AsyncWrite for File {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        if self.seek.is_some() && self.as_mut().poll_complete(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        self.discard_buffer()?;
+        let len = match self.rate_limit.poll_acquire(cx, buf.len()) {
+            Poll::Ready(len) => len,
+            Poll::Pending => return Poll::Pending,
+        };
+        let res = self.inner.write(&buf[..len]);
+        match res {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.rate_limit.blocked(len);
+                aio::set_waker(&self.aio, cx)?;
+                Poll::Pending
+            }
+            Err(e) => {
+                self.rate_limit.consume(0);
+                Poll::Ready(Err(e))
+            }
+            Ok(val) => {
+                self.rate_limit.consume(val);
+                self.transferred += val as u64;
+                self.unsynced |= val > 0;
+                Poll::Ready(Ok(val))
+            }
+        }
+    }
+
+    fn poll_write_buf<B: Buf>(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut B,
+    ) -> Poll<Result<usize, io::Error>> {
+        if !buf.has_remaining() {
+            return Poll::Ready(Ok(0));
+        }
+        let mut slices = [IoSlice::new(&[]); 64];
+        let count = buf.bytes_vectored(&mut slices);
+        // Every libssh2 write is at least one sftp packet, so gather small
+        // slices into one write. An unchanged `buf` gathers the same bytes
+        // again, which keeps retries after `WouldBlock` valid for libssh2.
+        let res = if count > 1 {
+            let mut data = Vec::with_capacity(BUFFER_SIZE);
+            for slice in &slices[..count] {
+                let len = cmp::min(slice.len(), BUFFER_SIZE - data.len());
+                data.extend_from_slice(&slice[..len]);
+                if data.len() == BUFFER_SIZE {
+                    break;
+                }
+            }
+            self.poll_write(cx, &data)
+        } else {
+            self.poll_write(cx, buf.bytes())
+        };
+        match res {
+            Poll::Ready(Ok(n)) => {
+                buf.advance(n);
+                Poll::Ready(Ok(n))
+            }
+            res => res,
+        }
+    }
+
+    /// libssh2 only reports written bytes once the server has acknowledged
+    /// them, so nothing is buffered on this side and flushing only waits
+    /// for the server to [`fsync`](File::fsync) the file if
+    /// [`set_sync_on_flush`](File::set_sync_on_flush) is enabled.
+    ///
+    /// A write that returned `Pending` and is not retried may still have
+    /// been sent in part; flushing does not cover those bytes.
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        if !(self.sync_on_flush && self.unsynced) {
+            return Poll::Ready(Ok(()));
+        }
+        match self.inner.fsync().map_err(io::Error::from) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                aio::set_waker(&self.aio, cx)?;
+                Poll::Pending
+            }
+            Err(e) => Poll::Ready(Err(e)),
+            Ok(()) => {
+                self.unsynced = false;
+                Poll::Ready(Ok(()))
+            }
+        }
+    }
+
+    /// Closes the remote handle once the server has acknowledged every
+    /// write, so the file can no longer be used afterwards.
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        if self.as_mut().poll_flush(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        if self.closed {
+            return Poll::Ready(Ok(()));
+        }
+        match self.inner.close().map_err(io::Error::from) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                aio::set_waker(&self.aio, cx)?;
+                Poll::Pending
+            }
+            Err(e) => {
+                self.closed = true;
+                self.close_failed = true;
+                Poll::Ready(Err(e))
+            }
+            Ok(()) => {
+                self.closed = true;
+                Poll::Ready(Ok(()))
+            }
+        }
+    }
+}
+
+impl AsyncBufRead for File {
+    fn poll_fill_buf(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        if self.seek.is_some() && self.as_mut().poll_complete(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        let this = self.get_mut();
+        if this.pos == this.filled {
+            if this.buf.is_empty() {
+                this.buf = vec![0; BUFFER_SIZE];
+            }
+            let len = this.read_len(this.buf.len());
+            let len = match this.rate_limit.poll_acquire(cx, len) {
+                Poll::Ready(len) => len,
+                Poll::Pending => return Poll::Pending,
+            };
+            match this.inner.read(&mut this.buf[..len]) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    this.read_timeout.poll_blocked(cx)?;
+                    aio::set_waker(&this.aio, cx)?;
                     return Poll::Pending;
                 }
                 Err(e) => return Poll::Ready(Err(e)),
-                Ok(val) => return Poll::Ready(Ok(val)),
+                Ok(n) => {
+                    this.read_timeout.reset();
+                    this.rate_limit.consume(n);
+                    this.pos = 0;
+                    this.filled = n;
+                }
             }
         }
+        Poll::Ready(Ok(&this.buf[this.pos..this.filled]))
     }
 
-    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
-        loop {
-            let res = self.inner.flush();
-            match res {
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        let pos = cmp::min(self.pos + amt, self.filled);
+        self.transferred += (pos - self.pos) as u64;
+        self.pos = pos;
+    }
+}
+
+impl AsyncSeek for File {
+    fn start_seek(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        position: SeekFrom,
+    ) -> Poll<io::Result<()>> {
+        // The seek itself is applied in `poll_complete`; reads and writes
+        // issued before that drive it to completion first.
+        self.discard_buffer()?;
+        self.seek = Some(position);
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let position = self.seek.take().unwrap_or(SeekFrom::Current(0));
+        // libssh2 keeps the file pointer locally, so only seeking relative to
+        // the end needs a round trip to learn the file size. `ssh2::File::seek`
+        // would do that stat itself but hides `WouldBlock`, so do it here.
+        let target = match position {
+            SeekFrom::Start(pos) => pos,
+            SeekFrom::Current(offset) => {
+                let current = self.inner.seek(SeekFrom::Current(0))?;
+                seek_target(current, offset)?
+            }
+            SeekFrom::End(offset) => match self.inner.stat().map_err(io::Error::from) {
                 Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    if let Some(ref aio) = *self.aio {
-                        aio.set_waker(cx)?;
-                    }
+                    self.seek = Some(position);
+                    aio::set_waker(&self.aio, cx)?;
                     return Poll::Pending;
                 }
                 Err(e) => return Poll::Ready(Err(e)),
-                Ok(val) => return Poll::Ready(Ok(val)),
+                Ok(stat) => match stat.size {
+                    Some(size) => seek_target(size, offset)?,
+                    None => return Poll::Ready(Err(io::Error::other("no file size available"))),
+                },
+            },
+        };
+        Poll::Ready(self.inner.seek(SeekFrom::Start(target)))
+    }
+}
+
+/// Applies a relative seek `offset` to `base`, rejecting positions before
+/// the start of the file or past `u64::MAX`.
+fn seek_target(base: u64, offset: i64) -> io::Result<u64> {
+    base.checked_add_signed(offset).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid seek to a negative or overflowing position",
+        )
+    })
+}
+
+impl BufferedReader {
+    /// Default capacity of the internal buffer.
+    pub const DEFAULT_CAPACITY: usize = 256 * 1024;
+
+    /// Creates a reader with a buffer of [`DEFAULT_CAPACITY`] bytes.
+    ///
+    /// [`DEFAULT_CAPACITY`]: BufferedReader::DEFAULT_CAPACITY
+    pub fn new(file: File) -> Self {
+        Self::with_capacity(Self::DEFAULT_CAPACITY, file)
+    }
+
+    /// Creates a reader with a buffer of `capacity` bytes.
+    pub fn with_capacity(capacity: usize, file: File) -> Self {
+        Self {
+            file,
+            buf: vec![0; capacity].into_boxed_slice(),
+            pos: 0,
+            filled: 0,
+        }
+    }
+
+    /// Returns a reference to the underlying file.
+    pub fn get_ref(&self) -> &File {
+        &self.file
+    }
+
+    /// Returns a mutable reference to the underlying file.
+    ///
+    /// Reading from or seeking the file directly will get it out of step
+    /// with the buffered data.
+    pub fn get_mut(&mut self) -> &mut File {
+        &mut self.file
+    }
+
+    /// Unwraps the underlying file, discarding any buffered data.
+    pub fn into_inner(self) -> File {
+        self.file
+    }
+}
+
+impl BufferedWriter {
+    /// Default capacity of the internal buffer.
+    pub const DEFAULT_CAPACITY: usize = 256 * 1024;
+
+    /// Creates a writer with a buffer of [`DEFAULT_CAPACITY`] bytes.
+    ///
+    /// [`DEFAULT_CAPACITY`]: BufferedWriter::DEFAULT_CAPACITY
+    pub fn new(file: File) -> Self {
+        Self::with_capacity(Self::DEFAULT_CAPACITY, file)
+    }
+
+    /// Creates a writer with a buffer of `capacity` bytes.
+    pub fn with_capacity(capacity: usize, file: File) -> Self {
+        Self {
+            file,
+            buf: Vec::with_capacity(capacity),
+            written: 0,
+            capacity,
+        }
+    }
+
+    /// Returns a reference to the underlying file.
+    pub fn get_ref(&self) -> &File {
+        &self.file
+    }
+
+    /// Returns a mutable reference to the underlying file.
+    ///
+    /// Writing to or seeking the file directly while data is buffered will
+    /// get the writes out of order.
+    pub fn get_mut(&mut self) -> &mut File {
+        &mut self.file
+    }
+
+    /// Returns the data that has not been sent yet.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf[self.written..]
+    }
+
+    /// Unwraps the underlying file, discarding any buffered data; flush
+    /// first to keep it.
+    pub fn into_inner(self) -> File {
+        self.file
+    }
+
+    /// Sends the buffered data and closes the file. See [`File::close`].
+    pub async fn close(mut self) -> Result<(), Error> {
+        self.flush().await?;
+        self.file.close().await
+    }
+
+    fn poll_flush_buf(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // The unsent part of the buffer only shrinks once the server has
+        // acknowledged it, so a write that would block is retried with the
+        // same data, as libssh2 requires.
+        while self.written < self.buf.len() {
+            match Pin::new(&mut self.file).poll_write(cx, &self.buf[self.written..])? {
+                Poll::Ready(0) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "failed to write the buffered data",
+                    )))
+                }
+                Poll::Ready(n) => self.written += n,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        self.buf.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for BufferedWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.buf.len() + buf.len() > self.capacity && self.poll_flush_buf(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        // Large writes gain nothing from going through the buffer.
+        if buf.len() >= self.capacity {
+            return Pin::new(&mut self.file).poll_write(cx, buf);
+        }
+        self.buf.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.poll_flush_buf(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        Pin::new(&mut self.file).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.poll_flush_buf(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        Pin::new(&mut self.file).poll_shutdown(cx)
+    }
+}
+
+impl AsyncRead for BufferedReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        // Large reads gain nothing from going through the buffer.
+        if self.pos == self.filled && buf.len() >= self.buf.len() {
+            return Pin::new(&mut self.file).poll_read(cx, buf);
+        }
+        let n = match self.as_mut().poll_fill_buf(cx)? {
+            Poll::Ready(available) => {
+                let n = cmp::min(available.len(), buf.len());
+                buf[..n].copy_from_slice(&available[..n]);
+                n
+            }
+            Poll::Pending => return Poll::Pending,
+        };
+        self.consume(n);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl AsyncBufRead for BufferedReader {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        if this.pos == this.filled {
+            match Pin::new(&mut this.file).poll_read(cx, &mut this.buf)? {
+                Poll::Ready(n) => {
+                    this.pos = 0;
+                    this.filled = n;
+                }
+                Poll::Pending => return Poll::Pending,
             }
         }
+        Poll::Ready(Ok(&this.buf[this.pos..this.filled]))
     }
 
-    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
-        Poll::Ready(Ok(().into()))
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        self.pos = cmp::min(self.pos + amt, self.filled);
     }
 }
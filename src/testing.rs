@@ -0,0 +1,201 @@
+//! A throwaway sshd to run integration tests against.
+//!
+//! Available with the `testing` feature. [`TestServer::start`] generates
+//! fresh host and user keys in a scratch directory and starts the system's
+//! OpenSSH server on a free local port, running as the current user with
+//! the internal sftp server enabled. Sessions from
+//! [`TestServer::session`] are already authenticated, and the files they
+//! see are the local ones, so a test can check an upload with `std::fs`.
+//!
+//! `sshd` and `ssh-keygen` must be installed; the absolute path to `sshd`
+//! can be overridden with the `ASYNC_SSH2_SSHD` environment variable.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), async_ssh2::Error> {
+//! use async_ssh2::testing::TestServer;
+//!
+//! let server = TestServer::start().await?;
+//! let sess = server.session().await?;
+//! let sftp = sess.sftp().await?;
+//! sftp.mkdir(&server.dir().join("data"), 0o755).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{Error, Session};
+use std::{
+    env, fmt,
+    fs::{self, File},
+    io,
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{self, Child, Command, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+use tokio::time;
+
+/// How long [`TestServer::start`] waits for sshd to accept connections.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A running sshd that is stopped, and its scratch directory removed, on
+/// drop. See the [module docs](self).
+pub struct TestServer {
+    dir: PathBuf,
+    addr: SocketAddr,
+    user: String,
+    child: Child,
+}
+
+impl TestServer {
+    /// Generates keys and a config, starts sshd and waits until it accepts
+    /// connections.
+    pub async fn start() -> Result<TestServer, Error> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let user = env::var("USER")
+            .or_else(|_| env::var("LOGNAME"))
+            .map_err(|_| io::Error::new(io::ErrorKind::NotFound, "USER is not set"))?;
+        let dir = env::temp_dir().join(format!(
+            "async-ssh2-sshd-{}-{}",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir)?;
+        let (addr, child) = match spawn_sshd(&dir) {
+            Ok(spawned) => spawned,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&dir);
+                return Err(e.into());
+            }
+        };
+        let mut server = TestServer {
+            dir,
+            addr,
+            user,
+            child,
+        };
+
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        loop {
+            if TcpStream::connect(server.addr).is_ok() {
+                return Ok(server);
+            }
+            if server.child.try_wait()?.is_some() || Instant::now() > deadline {
+                return Err(io::Error::other(format!(
+                    "sshd did not start: {}",
+                    server.log().unwrap_or_default().trim_end()
+                ))
+                .into());
+            }
+            time::delay_for(Duration::from_millis(50)).await;
+        }
+    }
+
+    /// Returns the address sshd listens on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns the user that sessions authenticate as.
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+
+    /// Returns the private key that the server accepts for [`user`].
+    ///
+    /// [`user`]: TestServer::user
+    pub fn private_key(&self) -> PathBuf {
+        self.dir.join("id_rsa")
+    }
+
+    /// Returns the scratch directory, which tests can use for their files.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns what sshd has logged so far, for diagnosing failed tests.
+    pub fn log(&self) -> io::Result<String> {
+        fs::read_to_string(self.dir.join("sshd.log"))
+    }
+
+    /// Connects to the server and authenticates with
+    /// [`private_key`](TestServer::private_key).
+    pub async fn session(&self) -> Result<Session, Error> {
+        let sess = Session::connect(self.addr).await?;
+        sess.userauth_pubkey_file(&self.user, None, &self.private_key(), None)
+            .await?;
+        Ok(sess)
+    }
+}
+
+impl fmt::Debug for TestServer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TestServer")
+            .field("addr", &self.addr)
+            .field("user", &self.user)
+            .field("dir", &self.dir)
+            .finish()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn spawn_sshd(dir: &Path) -> io::Result<(SocketAddr, Child)> {
+    keygen(&dir.join("ssh_host_rsa_key"))?;
+    keygen(&dir.join("id_rsa"))?;
+    fs::copy(dir.join("id_rsa.pub"), dir.join("authorized_keys"))?;
+    fs::write(
+        dir.join("sshd_config"),
+        format!(
+            "AuthorizedKeysFile {dir}/authorized_keys\n\
+             HostKey {dir}/ssh_host_rsa_key\n\
+             PidFile {dir}/sshd.pid\n\
+             Subsystem sftp internal-sftp\n\
+             UsePAM no\n\
+             HostKeyAlgorithms +ssh-rsa\n\
+             PubkeyAcceptedKeyTypes +ssh-rsa\n\
+             StrictModes no\n\
+             AllowTcpForwarding yes\n\
+             MaxStartups 500\n",
+            dir = dir.display()
+        ),
+    )?;
+
+    // Grab a free port; another process could take it before sshd does,
+    // which then shows up as sshd exiting early.
+    let addr = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?.local_addr()?;
+    let sshd = env::var_os("ASYNC_SSH2_SSHD").unwrap_or_else(|| "/usr/sbin/sshd".into());
+    let child = Command::new(&sshd)
+        .arg("-D")
+        .arg("-e")
+        .arg("-p")
+        .arg(addr.port().to_string())
+        .arg("-f")
+        .arg(dir.join("sshd_config"))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(File::create(dir.join("sshd.log"))?)
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", Path::new(&sshd).display(), e)))?;
+    Ok((addr, child))
+}
+
+fn keygen(path: &Path) -> io::Result<()> {
+    let status = Command::new("ssh-keygen")
+        // libssh2 only reads the older PEM format.
+        .args(["-q", "-t", "rsa", "-m", "PEM", "-N", "", "-f"])
+        .arg(path)
+        .stdin(Stdio::null())
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("ssh-keygen failed: {}", status)));
+    }
+    Ok(())
+}
@@ -1,33 +1,259 @@
+use crate::Error;
+use std::{
+    cmp,
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::time::{self, Delay, Instant};
+
 #[macro_export]
 macro_rules! into_the_future {
     ($aio:ident; $cb:expr) => {{
         struct ScopedFuture<'a, R, F: FnMut() -> Result<R, ssh2::Error>> {
             cb: &'a mut F,
             aio: Arc<Option<Aio>>,
+            // Set while libssh2 is partway through the call.
+            in_flight: bool,
+            // Set while libssh2 is partway through closing dropped handles.
+            closing: bool,
         }
 
         impl<'a, R, F: FnMut() -> Result<R, ssh2::Error>> Future for ScopedFuture<'a, R, F> {
             type Output = Result<R, Error>;
 
             fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+                if !self.in_flight && (*self.aio).as_ref().is_some_and(Aio::is_poisoned) {
+                    return Poll::Ready(Err(Error::Poisoned));
+                }
+                // Handles dropped while open are closed first, so that they
+                // never interleave with another request. Once the call has
+                // started they wait for the next one.
+                if !self.in_flight
+                    && (*self.aio)
+                        .as_ref()
+                        .is_some_and(|aio| !aio.close_deferred())
+                {
+                    self.closing = true;
+                    $crate::aio::set_waker(&self.aio, cx).map_err(Error::from)?;
+                    return Poll::Pending;
+                }
+                self.closing = false;
                 match (&mut self.cb)() {
                     Err(e)
                         if io::Error::from(ssh2::Error::from_errno(e.code())).kind()
                             == io::ErrorKind::WouldBlock =>
                     {
-                        if let Some(ref aio) = *self.aio {
-                            aio.set_waker(cx).map_err(Error::from)?;
-                        }
+                        self.in_flight = true;
+                        $crate::aio::set_waker(&self.aio, cx).map_err(Error::from)?;
                         return Poll::Pending;
                     }
-                    Err(e) => return Poll::Ready(Err(Error::from(e))),
-                    Ok(val) => return Poll::Ready(Ok(val)),
+                    Err(e) => {
+                        self.in_flight = false;
+                        return Poll::Ready(Err(Error::from(e)));
+                    }
+                    Ok(val) => {
+                        self.in_flight = false;
+                        return Poll::Ready(Ok(val));
+                    }
                 }
             }
         }
 
-        let f = ScopedFuture { cb: $cb, aio: $aio };
+        impl<'a, R, F: FnMut() -> Result<R, ssh2::Error>> Drop for ScopedFuture<'a, R, F> {
+            fn drop(&mut self) {
+                if let (true, Some(aio)) = (self.in_flight || self.closing, &*self.aio) {
+                    aio.poison();
+                }
+            }
+        }
 
-        f.await
+        let timeout = (*$aio).as_ref().and_then(Aio::timeout);
+        let f = ScopedFuture {
+            cb: $cb,
+            aio: $aio,
+            in_flight: false,
+            closing: false,
+        };
+
+        match timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, f).await {
+                Ok(res) => res,
+                Err(_) => Err(Error::Timeout(timeout)),
+            },
+            None => f.await,
+        }
     }};
 }
+
+/// Runs `future`, failing with [`Error::Timeout`] if it does not complete
+/// within `duration`.
+///
+/// This works with any future from this crate and follows the same rules as
+/// the [session timeout](crate::Session::set_timeout), which still applies
+/// underneath, so whichever is shorter wins. An operation cut short once it
+/// has started talking to the server leaves the session
+/// [poisoned](crate::Session::is_poisoned); one that never got to run does
+/// not.
+pub async fn timeout<T, F>(duration: Duration, future: F) -> Result<T, Error>
+where
+    F: Future<Output = Result<T, Error>>,
+{
+    match time::timeout(duration, future).await {
+        Ok(res) => res,
+        Err(_) => Err(Error::Timeout(duration)),
+    }
+}
+
+/// Encodes `data` as base64 without padding, as OpenSSH prints key
+/// fingerprints.
+pub(crate) fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | u32::from(b) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+    }
+    out
+}
+
+/// Fails reads that make no progress within a configured time.
+#[derive(Default)]
+pub(crate) struct ReadTimeout {
+    timeout: Option<Duration>,
+    delay: Option<Delay>,
+}
+
+impl ReadTimeout {
+    pub fn get(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub fn set(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+        self.delay = None;
+    }
+
+    /// To be called whenever a read would block. Starts the timer on the
+    /// first call and fails once it has expired.
+    pub fn poll_blocked(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return Ok(()),
+        };
+        let delay = self.delay.get_or_insert_with(|| time::delay_for(timeout));
+        match Pin::new(delay).poll(cx) {
+            Poll::Ready(()) => {
+                self.delay = None;
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("no data received for {:?}", timeout),
+                ))
+            }
+            Poll::Pending => Ok(()),
+        }
+    }
+
+    /// To be called whenever a read made progress.
+    pub fn reset(&mut self) {
+        self.delay = None;
+    }
+}
+
+/// Limits reads and writes to a number of bytes per second with a token
+/// bucket that holds up to one second's worth of bytes.
+#[derive(Default)]
+pub(crate) struct RateLimit {
+    rate: Option<u64>,
+    tokens: u64,
+    // Time up to which tokens have been added.
+    refilled: Option<Instant>,
+    delay: Option<Delay>,
+    // Length granted to a write that would block; libssh2 must be given the
+    // same data again, so the next call gets the same length.
+    pending: Option<usize>,
+}
+
+impl RateLimit {
+    pub fn get(&self) -> Option<u64> {
+        self.rate
+    }
+
+    pub fn set(&mut self, rate: Option<u64>) {
+        self.rate = rate.filter(|&rate| rate > 0);
+        self.tokens = self.rate.unwrap_or(0);
+        self.refilled = Some(Instant::now());
+        self.delay = None;
+    }
+
+    /// Returns how many of `len` bytes may be transferred now, waiting for
+    /// the bucket to refill if it is empty.
+    pub fn poll_acquire(&mut self, cx: &mut Context<'_>, len: usize) -> Poll<usize> {
+        let rate = match self.rate {
+            Some(rate) if len > 0 => rate,
+            _ => return Poll::Ready(len),
+        };
+        if let Some(pending) = self.pending {
+            return Poll::Ready(cmp::min(pending, len));
+        }
+        self.refill(rate);
+        // The timer may fire slightly early, so check again once it has.
+        while self.tokens == 0 {
+            // Wait for about 50ms of traffic rather than for every byte.
+            let want = cmp::min(len as u64, cmp::max(rate / 20, 1));
+            let deadline = self.refilled.unwrap() + nanos_for(want, rate);
+            let delay = self
+                .delay
+                .get_or_insert_with(|| time::delay_until(deadline));
+            if Pin::new(delay).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.delay = None;
+            self.refill(rate);
+        }
+        Poll::Ready(cmp::min(len as u64, self.tokens) as usize)
+    }
+
+    /// To be called when a write of `len` granted bytes would block.
+    pub fn blocked(&mut self, len: usize) {
+        if self.rate.is_some() {
+            self.pending = Some(len);
+        }
+    }
+
+    /// To be called with the number of bytes actually transferred.
+    pub fn consume(&mut self, n: usize) {
+        self.pending = None;
+        self.tokens = self.tokens.saturating_sub(n as u64);
+    }
+
+    fn refill(&mut self, rate: u64) {
+        let now = Instant::now();
+        let refilled = *self.refilled.get_or_insert(now);
+        let elapsed = now.saturating_duration_since(refilled);
+        let new = (elapsed.as_nanos() * u128::from(rate) / 1_000_000_000) as u64;
+        if self.tokens + new >= rate {
+            self.tokens = rate;
+            self.refilled = Some(now);
+        } else if new > 0 {
+            // Only advance by the time the whole tokens took, so that
+            // fractions carry over to the next refill.
+            self.tokens += new;
+            self.refilled = Some(refilled + nanos_for(new, rate));
+        }
+    }
+}
+
+/// Time it takes to accumulate `bytes` at `rate` bytes per second, rounded
+/// up.
+fn nanos_for(bytes: u64, rate: u64) -> Duration {
+    let nanos = (u128::from(bytes) * 1_000_000_000).div_ceil(u128::from(rate));
+    Duration::from_nanos(nanos as u64)
+}
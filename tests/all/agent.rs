@@ -1,5 +1,4 @@
 use async_ssh2::Session;
-use tokio;
 
 #[tokio::test]
 async fn smoke() {
@@ -10,7 +9,7 @@ async fn smoke() {
     {
         let a = agent.identities().unwrap();
         let i1 = &a[0];
-        assert!(agent.userauth("foo", &i1).await.is_err());
+        assert!(agent.userauth("foo", i1).await.is_err());
     }
     agent.disconnect().await.unwrap();
 }
@@ -1,8 +1,9 @@
-use async_ssh2::Channel;
+use async_ssh2::{Channel, Error, PtyModeOpcode, PtyModes};
 use std::{
-    io::prelude::*,
+    io::{self, prelude::*},
     net::{TcpListener, TcpStream},
     thread,
+    time::{Duration, Instant},
 };
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
@@ -15,7 +16,7 @@ async fn consume_stdio(channel: &mut Channel) -> (String, String) {
     channel.read_to_string(&mut stdout).await.unwrap();
 
     let mut stderr = String::new();
-    channel.stderr().read_to_string(&mut stderr).unwrap();
+    channel.stderr().read_to_string(&mut stderr).await.unwrap();
 
     eprintln!("stdout: {}", stdout);
     eprintln!("stderr: {}", stderr);
@@ -44,6 +45,7 @@ async fn smoke() {
     channel.close().await.unwrap();
     channel.wait_close().await.unwrap();
     assert_eq!(channel.exit_status().unwrap(), 0);
+    assert!(channel.exit_signal().unwrap().exit_signal.is_none());
     assert!(channel.eof());
 }
 
@@ -64,6 +66,22 @@ async fn bad_smoke() {
     assert!(channel.eof());
 }
 
+#[tokio::test]
+async fn read_timeout() {
+    let sess = crate::authed_session().await;
+    let mut channel = sess.channel_session().await.unwrap();
+    channel.exec("sleep 5").await.unwrap();
+    channel.set_read_timeout(Some(Duration::from_millis(100)));
+    assert_eq!(
+        channel.stderr().read_timeout(),
+        Some(Duration::from_millis(100))
+    );
+
+    let mut buf = [0; 1];
+    let err = channel.read(&mut buf).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+}
+
 #[tokio::test]
 async fn reading_data() {
     let sess = crate::authed_session().await;
@@ -74,6 +92,37 @@ async fn reading_data() {
     assert_eq!(output, "foo\n");
 }
 
+#[tokio::test]
+async fn window_size() {
+    let sess = crate::authed_session().await;
+    let mut channel = sess
+        .channel_session_with_window(16 * 1024 * 1024, 32 * 1024)
+        .await
+        .unwrap();
+    assert_eq!(channel.read_window().window_size_initial, 16 * 1024 * 1024);
+    channel.exec("head -c 4000000 /dev/zero").await.unwrap();
+
+    let mut output = Vec::new();
+    channel.read_to_end(&mut output).await.unwrap();
+    assert_eq!(output.len(), 4_000_000);
+}
+
+#[tokio::test]
+async fn empty_read() {
+    let sess = crate::authed_session().await;
+    let mut channel = sess.channel_session().await.unwrap();
+    channel.exec("sleep 1; echo foo").await.unwrap();
+    // Returns at once rather than waiting for output or EOF.
+    let n = tokio::time::timeout(Duration::from_millis(500), channel.read(&mut []))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(n, 0);
+
+    let (output, _) = consume_stdio(&mut channel).await;
+    assert_eq!(output, "foo\n");
+}
+
 #[tokio::test]
 async fn handle_extended_data() {
     let sess = crate::authed_session().await;
@@ -91,6 +140,17 @@ async fn handle_extended_data() {
     assert!(output.ends_with("foo\n"));
 }
 
+#[tokio::test]
+async fn reading_stderr() {
+    let sess = crate::authed_session().await;
+    let mut channel = sess.channel_session().await.unwrap();
+    channel.exec("echo foo; echo bar >&2").await.unwrap();
+
+    let (stdout, stderr) = consume_stdio(&mut channel).await;
+    assert_eq!(stdout, "foo\n");
+    assert!(stderr.ends_with("bar\n"));
+}
+
 #[tokio::test]
 async fn writing_data() {
     let sess = crate::authed_session().await;
@@ -114,6 +174,66 @@ async fn eof() {
     assert_eq!(output, "");
 }
 
+#[tokio::test]
+async fn shutdown() {
+    let sess = crate::authed_session().await;
+    let mut channel = sess.channel_session().await.unwrap();
+    channel.exec("cat").await.unwrap();
+    let mut input: &[u8] = b"foo\nbar\n";
+    tokio::io::copy(&mut input, &mut channel).await.unwrap();
+    channel.shutdown().await.unwrap();
+    channel.shutdown().await.unwrap();
+
+    let (output, _) = consume_stdio(&mut channel).await;
+    assert_eq!(output, "foo\nbar\n");
+    assert_eq!(channel.bytes_transferred(), 16);
+    channel.close().await.unwrap();
+    channel.wait_close().await.unwrap();
+    assert_eq!(channel.exit_status().unwrap(), 0);
+}
+
+#[tokio::test]
+async fn rate_limit() {
+    let sess = crate::authed_session().await;
+    let mut channel = sess.channel_session().await.unwrap();
+    assert_eq!(channel.rate_limit(), None);
+    channel.set_rate_limit(Some(10_000));
+    assert_eq!(channel.rate_limit(), Some(10_000));
+    channel.exec("cat").await.unwrap();
+
+    // A second's worth goes out at once, the remaining 30000 bytes written
+    // and read back take three seconds.
+    let start = Instant::now();
+    let data = vec![b'x'; 20_000];
+    channel.write_all(&data).await.unwrap();
+    channel.send_eof().await.unwrap();
+    let mut output = Vec::new();
+    channel.read_to_end(&mut output).await.unwrap();
+    assert_eq!(output, data);
+    assert!(start.elapsed() >= Duration::from_millis(2900));
+    channel.wait_close().await.unwrap();
+}
+
+#[tokio::test]
+async fn subsystem() {
+    let sess = crate::authed_session().await;
+    let mut channel = sess.channel_subsystem("sftp").await.unwrap();
+    // SSH_FXP_INIT for version 3, answered by SSH_FXP_VERSION
+    channel
+        .write_all(&[0, 0, 0, 5, 1, 0, 0, 0, 3])
+        .await
+        .unwrap();
+    let mut header = [0; 5];
+    channel.read_exact(&mut header).await.unwrap();
+    assert_eq!(header[4], 2);
+    channel.close().await.unwrap();
+
+    sess.channel_subsystem("no-such-subsystem")
+        .await
+        .err()
+        .unwrap();
+}
+
 #[tokio::test]
 async fn shell() {
     let sess = crate::authed_session().await;
@@ -132,10 +252,45 @@ async fn shell() {
 async fn setenv() {
     let sess = crate::authed_session().await;
     let mut channel = sess.channel_session().await.unwrap();
-    let _ = channel.setenv("FOO", "BAR").await;
+    match channel.setenv("FOO", "BAR").await {
+        Ok(()) => {}
+        // rejected by the server's AcceptEnv policy
+        Err(Error::SSH2(e)) if e.code() == -22 => {}
+        Err(e) => panic!("unexpected error: {}", e),
+    }
     channel.close().await.unwrap();
 }
 
+#[tokio::test]
+async fn send_signal() {
+    let sess = crate::authed_session().await;
+    let mut channel = sess.channel_session().await.unwrap();
+    channel.exec("sleep 100").await.unwrap();
+    channel.send_signal("TERM").await.unwrap();
+
+    consume_stdio(&mut channel).await;
+    channel.wait_eof().await.unwrap();
+    channel.close().await.unwrap();
+    channel.wait_close().await.unwrap();
+    let signal = channel.exit_signal().unwrap();
+    assert_eq!(signal.exit_signal.as_deref(), Some("TERM"));
+    assert_eq!(channel.exit_status().unwrap(), 0);
+}
+
+#[tokio::test]
+async fn request_auth_agent_forwarding() {
+    let sess = crate::authed_session().await;
+    let mut channel = sess.channel_session().await.unwrap();
+    channel.request_auth_agent_forwarding().await.unwrap();
+    channel.exec("test -n \"$SSH_AUTH_SOCK\"").await.unwrap();
+
+    consume_stdio(&mut channel).await;
+    channel.wait_eof().await.unwrap();
+    channel.close().await.unwrap();
+    channel.wait_close().await.unwrap();
+    assert_eq!(channel.exit_status().unwrap(), 0);
+}
+
 #[tokio::test]
 async fn direct() {
     let a = TcpListener::bind("127.0.0.1:0").unwrap();
@@ -143,7 +298,7 @@ async fn direct() {
     let t = thread::spawn(move || {
         let mut s = a.accept().unwrap().0;
         let mut b = [0, 0, 0];
-        s.read(&mut b).unwrap();
+        s.read_exact(&mut b).unwrap();
         assert_eq!(b, [1, 2, 3]);
         s.write_all(&[4, 5, 6]).unwrap();
     });
@@ -154,7 +309,7 @@ async fn direct() {
         .unwrap();
     channel.write_all(&[1, 2, 3]).await.unwrap();
     let mut r = [0, 0, 0];
-    channel.read(&mut r).await.unwrap();
+    channel.read_exact(&mut r).await.unwrap();
     assert_eq!(r, [4, 5, 6]);
     t.join().ok().unwrap();
 }
@@ -167,9 +322,9 @@ async fn forward() {
         .await
         .unwrap();
     let t = thread::spawn(move || {
-        let mut s = TcpStream::connect(&("127.0.0.1", port)).unwrap();
+        let mut s = TcpStream::connect(("127.0.0.1", port)).unwrap();
         let mut b = [0, 0, 0];
-        s.read(&mut b).unwrap();
+        s.read_exact(&mut b).unwrap();
         assert_eq!(b, [1, 2, 3]);
         s.write_all(&[4, 5, 6]).unwrap();
     });
@@ -177,7 +332,7 @@ async fn forward() {
     let mut channel = listen.accept().await.unwrap();
     channel.write_all(&[1, 2, 3]).await.unwrap();
     let mut r = [0, 0, 0];
-    channel.read(&mut r).await.unwrap();
+    channel.read_exact(&mut r).await.unwrap();
     assert_eq!(r, [4, 5, 6]);
     t.join().ok().unwrap();
 }
@@ -216,6 +371,39 @@ async fn nonblocking_before_exit_code() {
     assert!(channel.exit_status().unwrap() == 0);
 }
 
+#[tokio::test]
+async fn wait_exit_status() {
+    let sess = crate::authed_session().await;
+    let mut channel = sess.channel_session().await.unwrap();
+    channel.exec("echo foo; exit 3").await.unwrap();
+    let mut output = String::new();
+    channel.read_to_string(&mut output).await.unwrap();
+    assert_eq!(output, "foo\n");
+    assert_eq!(channel.wait_exit_status().await.unwrap(), 3);
+    assert_eq!(channel.read(&mut [0; 8]).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn read_after_server_hangup() {
+    let sess = crate::authed_session().await;
+    let mut channel = sess.channel_session().await.unwrap();
+    // Kill the sshd process serving the connection once the command has
+    // exited, so the close arrives just before the connection goes away.
+    channel
+        .exec("(sleep 1; kill $PPID) </dev/null >/dev/null 2>&1 & exit 3")
+        .await
+        .unwrap();
+    tokio::time::delay_for(Duration::from_secs(2)).await;
+
+    // stderr first, so that its read is the one that meets the hangup.
+    let mut output = Vec::new();
+    channel.stderr().read_to_end(&mut output).await.unwrap();
+    assert!(output.is_empty());
+    channel.read_to_end(&mut output).await.unwrap();
+    assert!(output.is_empty());
+    assert_eq!(channel.wait_exit_status().await.unwrap(), 3);
+}
+
 #[tokio::test]
 async fn exit_code_ignores_other_errors() {
     let sess = crate::authed_session().await;
@@ -224,30 +412,47 @@ async fn exit_code_ignores_other_errors() {
     channel.wait_eof().await.unwrap();
     channel.close().await.unwrap();
     channel.wait_close().await.unwrap();
-    let longdescription: String = ::std::iter::repeat('a').take(300).collect();
+    let longdescription = "a".repeat(300);
     assert!(sess.disconnect(None, &longdescription, None).await.is_err()); // max len == 256
     assert!(channel.exit_status().unwrap() == 0);
 }
 
-/*
-#[test]
-fn pty_modes_are_propagated() {
-    let sess = ::authed_session();
-    let mut channel = sess.channel_session().unwrap();
+#[tokio::test]
+async fn pty_modes_are_propagated() {
+    let sess = crate::authed_session().await;
+    let mut channel = sess.channel_session().await.unwrap();
     eprintln!("requesting pty");
 
-    let mut mode = ssh2::PtyModes::new();
+    let mut mode = PtyModes::new();
     // intr is typically CTRL-C; setting it to unmodified `y`
     // should be very high signal that it took effect
-    mode.set_character(ssh2::PtyModeOpcode::VINTR, Some('y'));
+    mode.set_character(PtyModeOpcode::VINTR, Some('y'));
 
-    channel.request_pty("xterm", Some(mode), None).unwrap();
-    channel.exec("stty -a").unwrap();
+    channel
+        .request_pty("xterm", Some(mode), None)
+        .await
+        .unwrap();
+    channel.exec("stty -a").await.unwrap();
 
-    let (out, _err) = consume_stdio(&mut channel);
-    channel.close().unwrap();
+    let (out, _err) = consume_stdio(&mut channel).await;
+    channel.close().await.unwrap();
 
     // This may well be linux specific
     assert!(out.contains("intr = y"), "mode was propagated");
 }
-*/
+
+#[tokio::test]
+async fn pty_size() {
+    let sess = crate::authed_session().await;
+    let mut channel = sess.channel_session().await.unwrap();
+    channel
+        .request_pty("xterm", None, Some((80, 24, 0, 0)))
+        .await
+        .unwrap();
+    channel.request_pty_size(100, 40, None, None).await.unwrap();
+    channel.exec("stty size").await.unwrap();
+
+    let (out, _err) = consume_stdio(&mut channel).await;
+    channel.close().await.unwrap();
+    assert_eq!(out.trim(), "40 100");
+}
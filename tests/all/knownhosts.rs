@@ -1,4 +1,5 @@
-use async_ssh2::{KnownHostFileKind, Session};
+use async_ssh2::{CheckResult, KnownHostFileKind, KnownHostKeyFormat, Session};
+use tempfile::tempdir;
 
 #[test]
 fn smoke() {
@@ -45,9 +46,65 @@ PW3RcT0eOzQgqlJL3RKrTJvdsjE3JEAvGq3lGHSZXy28G3skua2SmVi\
 
     assert_eq!(
         known_hosts
-            .write_string(&host, KnownHostFileKind::OpenSSH)
+            .write_string(host, KnownHostFileKind::OpenSSH)
             .unwrap(),
         encoded
     );
     known_hosts.remove(host).unwrap();
 }
+
+#[tokio::test]
+async fn check() {
+    let td = tempdir().unwrap();
+    let mut sess = Session::new().unwrap();
+    sess.set_tcp_stream(crate::socket()).unwrap();
+
+    let known_hosts = sess.known_hosts().unwrap();
+    assert!(matches!(
+        known_hosts.check("127.0.0.1"),
+        CheckResult::Failure
+    ));
+
+    sess.handshake().await.unwrap();
+    let (key, key_type) = sess.host_key().unwrap();
+    let port = crate::test_addr()
+        .rsplit(':')
+        .next()
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    // Build a fixture file holding the server's key and a bogus key for
+    // another host, then check against a fresh collection read from it.
+    let mut fixture = sess.known_hosts().unwrap();
+    let fmt = KnownHostKeyFormat::from(key_type);
+    let host = format!("[127.0.0.1]:{}", port);
+    fixture.add(&host, key, "server", fmt).unwrap();
+    let bogus: Vec<u8> = key.iter().map(|b| b ^ 0xff).collect();
+    let other = format!("[localhost]:{}", port);
+    fixture.add(&other, &bogus, "bogus", fmt).unwrap();
+    let path = td.path().join("known_hosts");
+    fixture
+        .write_file(&path, KnownHostFileKind::OpenSSH)
+        .unwrap();
+
+    let mut known_hosts = sess.known_hosts().unwrap();
+    assert_eq!(
+        known_hosts
+            .read_file(&path, KnownHostFileKind::OpenSSH)
+            .unwrap(),
+        2
+    );
+    assert!(matches!(
+        known_hosts.check_port("127.0.0.1", port),
+        CheckResult::Match
+    ));
+    assert!(matches!(
+        known_hosts.check_port("localhost", port),
+        CheckResult::Mismatch
+    ));
+    assert!(matches!(
+        known_hosts.check_port("example.com", port),
+        CheckResult::NotFound
+    ));
+}
@@ -8,8 +8,12 @@ use std::{env, net::TcpStream};
 mod agent;
 mod channel;
 mod knownhosts;
+mod pool;
+mod retry;
 mod session;
 mod sftp;
+#[cfg(feature = "testing")]
+mod testing;
 
 pub fn test_addr() -> String {
     let port = env::var("RUST_SSH2_FIXTURE_PORT")
@@ -20,7 +24,7 @@ pub fn test_addr() -> String {
 }
 
 pub fn socket() -> TcpStream {
-    TcpStream::connect(&test_addr()).unwrap()
+    TcpStream::connect(test_addr()).unwrap()
 }
 
 pub async fn authed_session() -> async_ssh2::Session {
@@ -36,7 +40,7 @@ pub async fn authed_session() -> async_ssh2::Session {
         agent.connect().await.unwrap();
         agent.list_identities().unwrap();
         let identity = &agent.identities().unwrap()[0];
-        agent.userauth(&user, &identity).await.unwrap();
+        agent.userauth(&user, identity).await.unwrap();
     }
     assert!(sess.authenticated());
     sess
@@ -0,0 +1,129 @@
+use async_ssh2::{Session, SessionPool};
+use std::{
+    net::{TcpListener, TcpStream},
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::time;
+
+#[test]
+fn pool_is_send_sync() {
+    fn must_be_send_sync<T: Send + Sync>(_: &T) -> bool {
+        true
+    }
+
+    let pool = SessionPool::new(1, || async { Session::new() });
+    assert!(must_be_send_sync(&pool));
+}
+
+#[tokio::test]
+async fn reuse_and_wait() {
+    // Pooled sessions need a live connection, though not a handshake.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let connects = Arc::new(AtomicUsize::new(0));
+    let pool = {
+        let connects = connects.clone();
+        SessionPool::new(2, move || {
+            connects.fetch_add(1, Ordering::SeqCst);
+            async move {
+                let mut sess = Session::new()?;
+                sess.set_tcp_stream(TcpStream::connect(addr)?)?;
+                Ok(sess)
+            }
+        })
+    };
+    assert_eq!(pool.size(), 2);
+
+    let a = pool.get().await.unwrap();
+    let b = pool.get().await.unwrap();
+    assert_eq!(connects.load(Ordering::SeqCst), 2);
+    assert!(time::timeout(Duration::from_millis(50), pool.get())
+        .await
+        .is_err());
+
+    drop(a);
+    let c = pool.clone().get().await.unwrap();
+    assert_eq!(connects.load(Ordering::SeqCst), 2);
+
+    // A discarded session is replaced by a new one.
+    c.discard();
+    let _d = pool.get().await.unwrap();
+    assert_eq!(connects.load(Ordering::SeqCst), 3);
+    drop(b);
+    drop(listener);
+}
+
+#[tokio::test]
+async fn one_waker_per_waiter() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let pool = SessionPool::new(1, move || async move {
+        let mut sess = Session::new()?;
+        sess.set_tcp_stream(TcpStream::connect(addr)?)?;
+        Ok(sess)
+    });
+
+    let a = pool.get().await.unwrap();
+    let mut waiting = Box::pin(pool.get());
+    for _ in 0..10 {
+        assert!(time::timeout(Duration::from_millis(1), &mut waiting)
+            .await
+            .is_err());
+    }
+    assert!(format!("{:?}", pool).contains("waiters: 1"));
+
+    // A waiter that gives up leaves the queue.
+    let mut other = Box::pin(pool.get());
+    assert!(time::timeout(Duration::from_millis(1), &mut other)
+        .await
+        .is_err());
+    assert!(format!("{:?}", pool).contains("waiters: 2"));
+    drop(other);
+    assert!(format!("{:?}", pool).contains("waiters: 1"));
+
+    drop(a);
+    let _b = waiting.await.unwrap();
+    assert!(format!("{:?}", pool).contains("waiters: 0"));
+    drop(listener);
+}
+
+#[tokio::test]
+async fn evicts_closed() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let connects = Arc::new(AtomicUsize::new(0));
+    let pool = {
+        let connects = connects.clone();
+        SessionPool::new(1, move || {
+            connects.fetch_add(1, Ordering::SeqCst);
+            async move {
+                let mut sess = Session::new()?;
+                sess.set_tcp_stream(TcpStream::connect(addr)?)?;
+                Ok(sess)
+            }
+        })
+    };
+
+    let sess = pool.get().await.unwrap();
+    // The server hangs up while the session sits idle in the pool.
+    drop(sess);
+    drop(listener.accept().unwrap().0);
+    time::delay_for(Duration::from_millis(50)).await;
+
+    let _sess = pool.get().await.unwrap();
+    assert_eq!(connects.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn sftp() {
+    let pool = SessionPool::new(2, || async { Ok(crate::authed_session().await) });
+    let sess = pool.get().await.unwrap();
+    let sftp = sess.sftp().await.unwrap();
+    assert!(sftp.stat(Path::new("/")).await.unwrap().is_dir());
+    sftp.shutdown().await.unwrap();
+}
@@ -0,0 +1,65 @@
+use async_ssh2::{retry, Error, RetryPolicy};
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+fn reset() -> Error {
+    io::Error::from(io::ErrorKind::ConnectionReset).into()
+}
+
+fn policy(max_attempts: u32) -> RetryPolicy {
+    RetryPolicy::new(max_attempts)
+        .with_backoff(Duration::from_millis(10), Duration::from_millis(15))
+}
+
+#[test]
+fn is_transient() {
+    assert!(reset().is_transient());
+    assert!(Error::Timeout(Duration::from_secs(1)).is_transient());
+    assert!(Error::Path("a".into(), Box::new(reset())).is_transient());
+    assert!(Error::SSH2(ssh2::Error::new(-43, "recv failed")).is_transient());
+    assert!(!Error::Poisoned.is_transient());
+    assert!(!Error::SSH2(ssh2::Error::new(-31, "sftp protocol error")).is_transient());
+    assert!(!Error::from(io::Error::from(io::ErrorKind::PermissionDenied)).is_transient());
+}
+
+#[tokio::test]
+async fn retries_transient_errors() {
+    let mut attempts = 0;
+    let start = Instant::now();
+    let res = retry(&policy(3), || {
+        attempts += 1;
+        let res = if attempts < 3 {
+            Err(reset())
+        } else {
+            Ok(attempts)
+        };
+        async move { res }
+    })
+    .await;
+    assert_eq!(res.unwrap(), 3);
+    // 10 ms, then 20 ms capped at 15 ms.
+    assert!(start.elapsed() >= Duration::from_millis(25));
+}
+
+#[tokio::test]
+async fn gives_up() {
+    let mut attempts = 0;
+    let res: Result<(), _> = retry(&policy(2), || {
+        attempts += 1;
+        async { Err(reset()) }
+    })
+    .await;
+    assert!(res.unwrap_err().is_transient());
+    assert_eq!(attempts, 2);
+
+    let mut attempts = 0;
+    let res: Result<(), _> = retry(&policy(5), || {
+        attempts += 1;
+        async { Err(io::Error::from(io::ErrorKind::NotFound).into()) }
+    })
+    .await;
+    assert!(!res.unwrap_err().is_transient());
+    assert_eq!(attempts, 1);
+}
@@ -1,6 +1,14 @@
-use async_ssh2::Session;
-use ssh2::{HashType, MethodType};
-use std::{env, fs::File, io::prelude::*, path::Path};
+use async_ssh2::{DisconnectCode, Error, HashType, MethodType, Session, TraceFlags};
+use bytes::buf::{Buf, BufExt};
+use std::{
+    env,
+    fs::File,
+    io::prelude::*,
+    net::{TcpListener, TcpStream},
+    path::Path,
+    thread,
+    time::Duration,
+};
 use tempfile::tempdir;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
@@ -14,25 +22,295 @@ fn session_is_send() {
     assert!(must_be_send(&sess));
 }
 
+#[test]
+fn handles_are_send_sync() {
+    fn must_be_send_sync<T: Send + Sync>() {}
+
+    must_be_send_sync::<Session>();
+    must_be_send_sync::<async_ssh2::Channel>();
+    must_be_send_sync::<async_ssh2::Sftp>();
+    must_be_send_sync::<async_ssh2::File>();
+}
+
+#[test]
+fn futures_are_send() {
+    fn must_be_send<T: Send>(_: &T) -> bool {
+        true
+    }
+
+    let sess = Session::new().unwrap();
+    assert!(must_be_send(&sess.sftp()));
+    assert!(must_be_send(&sess.channel_session()));
+}
+
+#[cfg(feature = "raw-access")]
+#[test]
+fn raw_access() {
+    let sess = Session::new().unwrap();
+    assert!(!sess.raw().is_blocking());
+    sess.raw().set_banner("foo").unwrap();
+}
+
 #[tokio::test]
 async fn smoke() {
     let sess = Session::new().unwrap();
     assert!(sess.banner_bytes().is_none());
-    sess.set_banner("foo").await.unwrap();
+    assert!(sess.banner().is_none());
+    sess.set_banner("foo").unwrap();
+    assert!(sess.last_error().is_none());
     assert!(!sess.is_blocking());
     assert_eq!(sess.timeout(), 0);
+    assert!(!sess.compress());
     sess.set_compress(true);
+    assert!(sess.compress());
     assert!(sess.host_key().is_none());
     sess.method_pref(MethodType::Kex, "diffie-hellman-group14-sha1")
-        .await
         .unwrap();
+    sess.method_pref(MethodType::CryptCs, "not-a-cipher")
+        .err()
+        .unwrap();
+    let (code, message) = sess.last_error().unwrap();
+    assert!(code < 0);
+    assert!(!message.is_empty());
+    sess.clear_last_error();
+    assert!(sess.last_error().is_none());
     assert!(sess.methods(MethodType::Kex).is_none());
     sess.set_timeout(0);
+    sess.trace(TraceFlags::KEX | TraceFlags::AUTH).unwrap();
+    sess.trace(TraceFlags::empty()).unwrap();
     sess.supported_algs(MethodType::Kex).unwrap();
     sess.supported_algs(MethodType::HostKey).unwrap();
     sess.channel_session().await.err().unwrap();
 }
 
+#[tokio::test]
+async fn blocking() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let mut sess = Session::new().unwrap();
+    assert!(!sess.is_blocking());
+    sess.set_blocking(true).unwrap();
+    assert!(sess.is_blocking());
+
+    sess.set_tcp_stream(TcpStream::connect(addr).unwrap())
+        .unwrap();
+    assert!(!sess.is_blocking());
+    match sess.set_blocking(true) {
+        Err(Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput),
+        res => panic!("unexpected result: {:?}", res),
+    }
+    assert!(!sess.is_blocking());
+    sess.set_blocking(false).unwrap();
+}
+
+#[tokio::test]
+async fn handshake_timeout() {
+    // a server that accepts the connection but never speaks
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let t = thread::spawn(move || {
+        let mut s = listener.accept().unwrap().0;
+        let _ = s.read_to_end(&mut Vec::new());
+    });
+
+    let mut sess = Session::new().unwrap();
+    sess.set_tcp_stream(TcpStream::connect(addr).unwrap())
+        .unwrap();
+    sess.set_timeout(100);
+    match sess.handshake().await {
+        Err(Error::Timeout(timeout)) => assert_eq!(timeout, Duration::from_millis(100)),
+        res => panic!("expected a timeout, got {:?}", res.map(|_| ())),
+    }
+    assert!(sess.is_poisoned());
+    match sess.handshake().await {
+        Err(Error::Poisoned) => {}
+        res => panic!("expected a poisoned session, got {:?}", res.map(|_| ())),
+    }
+    drop(sess);
+    t.join().unwrap();
+}
+
+#[tokio::test]
+async fn call_timeout() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let t = thread::spawn(move || {
+        let mut s = listener.accept().unwrap().0;
+        let _ = s.read_to_end(&mut Vec::new());
+    });
+
+    let mut sess = Session::new().unwrap();
+    sess.set_tcp_stream(TcpStream::connect(addr).unwrap())
+        .unwrap();
+    sess.set_timeout(60_000);
+    let timeout = Duration::from_millis(100);
+    match async_ssh2::timeout(timeout, sess.handshake()).await {
+        Err(Error::Timeout(t)) => assert_eq!(t, timeout),
+        res => panic!("expected a timeout, got {:?}", res.map(|_| ())),
+    }
+    assert!(sess.is_poisoned());
+    drop(sess);
+    t.join().unwrap();
+}
+
+#[tokio::test]
+async fn is_alive() {
+    assert!(!Session::new().unwrap().is_alive().await);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sess = Session::new().unwrap();
+    sess.set_tcp_stream(TcpStream::connect(listener.local_addr().unwrap()).unwrap())
+        .unwrap();
+    let peer = listener.accept().unwrap().0;
+    assert!(sess.is_alive().await);
+
+    drop(peer);
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while sess.is_alive().await {
+        assert!(std::time::Instant::now() < deadline);
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+async fn userauth_cert_unsupported() {
+    use std::io::ErrorKind;
+
+    let td = tempdir().unwrap();
+    let sess = Session::new().unwrap();
+    let key = td.path().join("id");
+    let cert_path = td.path().join("id-cert.pub");
+    std::fs::write(&key, "").unwrap();
+    for &(cert, kind) in &[
+        (
+            "ssh-ed25519-cert-v01@openssh.com AAAA user@host\n",
+            ErrorKind::Unsupported,
+        ),
+        ("ssh-ed25519 AAAA user@host\n", ErrorKind::InvalidInput),
+        ("", ErrorKind::InvalidInput),
+    ] {
+        std::fs::write(&cert_path, cert).unwrap();
+        match sess
+            .userauth_cert_file("user", &cert_path, &key, None)
+            .await
+        {
+            Err(Error::Path(path, e)) => {
+                assert_eq!(path, cert_path);
+                assert_eq!(std::io::Error::from(*e).kind(), kind);
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match sess.userauth_cert_memory("user", cert, "", None).await {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), kind),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
+
+#[tokio::test]
+async fn method_pref_unsatisfiable() {
+    let socket = crate::socket();
+    let mut sess = Session::new().unwrap();
+    sess.method_pref(MethodType::MacCs, "hmac-md5-96").unwrap();
+    sess.method_pref(MethodType::MacSc, "hmac-md5-96").unwrap();
+    sess.set_tcp_stream(socket).unwrap();
+    sess.handshake().await.err().unwrap();
+    assert!(sess.methods(MethodType::MacCs).is_none());
+}
+
+#[tokio::test]
+async fn compress() {
+    let socket = crate::socket();
+    let mut sess = Session::new().unwrap();
+    sess.set_compress(true);
+    sess.set_tcp_stream(socket).unwrap();
+    sess.handshake().await.unwrap();
+    assert!(sess
+        .methods(MethodType::CompCs)
+        .unwrap()
+        .starts_with("zlib"));
+    assert!(sess
+        .methods(MethodType::CompSc)
+        .unwrap()
+        .starts_with("zlib"));
+}
+
+#[tokio::test]
+async fn password_auth_failed() {
+    let user = env::var("USER").unwrap();
+    let socket = crate::socket();
+    let mut sess = Session::new().unwrap();
+    sess.set_tcp_stream(socket).unwrap();
+    sess.handshake().await.unwrap();
+    match sess.userauth_password(&user, "not the password").await {
+        Err(Error::AuthFailed(method, _)) => assert_eq!(method, "password"),
+        res => panic!("expected an auth failure, got {:?}", res),
+    }
+    assert!(!sess.authenticated());
+}
+
+#[tokio::test]
+async fn keyboard_interactive() {
+    let user = env::var("USER").unwrap();
+    let socket = crate::socket();
+    let mut sess = Session::new().unwrap();
+    sess.set_tcp_stream(socket).unwrap();
+    sess.handshake().await.unwrap();
+    let res = sess
+        .userauth_keyboard_interactive(&user, |prompts| {
+            prompts.iter().map(|_| "not the password".into()).collect()
+        })
+        .await;
+    match res {
+        Err(Error::AuthFailed(method, _)) => assert_eq!(method, "keyboard-interactive"),
+        res => panic!("expected an auth failure, got {:?}", res),
+    }
+    assert!(!sess.authenticated());
+}
+
+#[tokio::test]
+async fn auth_retry() {
+    let user = env::var("USER").unwrap();
+    let mut sess = Session::new().unwrap();
+    sess.set_tcp_stream(crate::socket()).unwrap();
+    sess.handshake().await.unwrap();
+
+    match sess.userauth_password(&user, "not the password").await {
+        Err(Error::AuthFailed(method, _)) => assert_eq!(method, "password"),
+        res => panic!("expected an auth failure, got {:?}", res),
+    }
+    // the server's host key is not authorized for the user
+    let key = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/sshd/ssh_host_rsa_key");
+    sess.userauth_pubkey_file(&user, None, &key, None)
+        .await
+        .err()
+        .unwrap();
+    assert!(!sess.authenticated());
+    assert!(!sess.is_poisoned());
+
+    let mut agent = sess.agent().unwrap();
+    agent.connect().await.unwrap();
+    agent.list_identities().unwrap();
+    let identity = &agent.identities().unwrap()[0];
+    agent.userauth(&user, identity).await.unwrap();
+    assert!(sess.authenticated());
+}
+
+#[tokio::test]
+async fn none_auth_failed() {
+    let user = env::var("USER").unwrap();
+    let socket = crate::socket();
+    let mut sess = Session::new().unwrap();
+    sess.set_tcp_stream(socket).unwrap();
+    sess.handshake().await.unwrap();
+    match sess.userauth_none(&user).await {
+        Err(Error::AuthFailed(method, _)) => assert_eq!(method, "none"),
+        res => panic!("expected an auth failure, got {:?}", res),
+    }
+    assert!(!sess.authenticated());
+}
+
 #[tokio::test]
 async fn smoke_handshake() {
     let user = env::var("USER").unwrap();
@@ -40,6 +318,10 @@ async fn smoke_handshake() {
     let mut sess = Session::new().unwrap();
     sess.set_tcp_stream(socket).unwrap();
     sess.handshake().await.unwrap();
+    assert!(sess.banner().unwrap().starts_with("SSH-2.0-"));
+    sess.methods(MethodType::Kex).unwrap();
+    sess.methods(MethodType::CryptCs).unwrap();
+    sess.methods(MethodType::MacSc).unwrap();
     sess.host_key().unwrap();
     let methods = sess.auth_methods(&user).await.unwrap();
     assert!(methods.contains("publickey"), "{}", methods);
@@ -50,10 +332,127 @@ async fn smoke_handshake() {
     agent.list_identities().unwrap();
     {
         let identity = &agent.identities().unwrap()[0];
-        agent.userauth(&user, &identity).await.unwrap();
+        agent.userauth(&user, identity).await.unwrap();
     }
     assert!(sess.authenticated());
     sess.host_key_hash(HashType::Md5).unwrap();
+    assert_eq!(sess.host_key_hash(HashType::Sha256).unwrap().len(), 32);
+}
+
+#[tokio::test]
+async fn connect() {
+    let sess = Session::connect(crate::test_addr()).await.unwrap();
+    assert!(sess.banner().unwrap().starts_with("SSH-2.0-"));
+    assert!(!sess.authenticated());
+
+    let sess = Session::connect_stream(crate::socket()).await.unwrap();
+    sess.host_key().unwrap();
+}
+
+#[tokio::test]
+async fn ipv6_stream() {
+    // a server that accepts the connection but never speaks
+    let listener = match TcpListener::bind("[::1]:0") {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("skipping, no IPv6 loopback: {}", e);
+            return;
+        }
+    };
+    let addr = listener.local_addr().unwrap();
+    let t = thread::spawn(move || {
+        let mut s = listener.accept().unwrap().0;
+        let _ = s.read_to_end(&mut Vec::new());
+    });
+
+    let mut sess = Session::new().unwrap();
+    sess.set_tcp_stream(TcpStream::connect(addr).unwrap())
+        .unwrap();
+    sess.set_timeout(100);
+    match sess.handshake().await {
+        Err(Error::Timeout(_)) => {}
+        res => panic!("expected a timeout, got {:?}", res.map(|_| ())),
+    }
+    drop(sess);
+    t.join().unwrap();
+}
+
+// Needs the fixture sshd to listen on the IPv6 loopback as well.
+#[tokio::test]
+#[ignore]
+async fn connect_ipv6() {
+    let port = crate::test_addr()
+        .rsplit(':')
+        .next()
+        .unwrap()
+        .parse()
+        .unwrap();
+    let sess = Session::connect(("::1", port)).await.unwrap();
+    sess.host_key().unwrap();
+    let sess = Session::connect(format!("[::1]:{}", port)).await.unwrap();
+    sess.host_key().unwrap();
+    let sess = Session::connect(std::net::SocketAddrV6::new(
+        std::net::Ipv6Addr::LOCALHOST,
+        port,
+        0,
+        0,
+    ))
+    .await
+    .unwrap();
+    sess.host_key().unwrap();
+}
+
+#[tokio::test]
+async fn connect_verified() {
+    let sess = Session::connect(crate::test_addr()).await.unwrap();
+    let fingerprint = sess.host_key_fingerprint().unwrap();
+    assert!(fingerprint.starts_with("SHA256:"));
+    assert_eq!(fingerprint.len(), "SHA256:".len() + 43);
+
+    let sess = Session::connect_verified(crate::test_addr(), &fingerprint)
+        .await
+        .unwrap();
+    assert_eq!(sess.host_key_fingerprint().unwrap(), fingerprint);
+    Session::connect_verified(crate::test_addr(), &fingerprint["SHA256:".len()..])
+        .await
+        .unwrap();
+
+    let wrong = "SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s";
+    match Session::connect_verified(crate::test_addr(), wrong).await {
+        Err(Error::HostKeyMismatch { expected, actual }) => {
+            assert_eq!(expected, wrong);
+            assert_eq!(actual, fingerprint);
+        }
+        res => panic!("unexpected result: {:?}", res.map(|_| ())),
+    }
+}
+
+#[tokio::test]
+async fn connect_refused() {
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    match Session::connect(addr).await {
+        Err(Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::ConnectionRefused),
+        res => panic!("unexpected result: {:?}", res.map(|_| ())),
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn set_stream() {
+    use std::os::unix::net::UnixStream;
+
+    let (a, _b) = UnixStream::pair().unwrap();
+    let mut sess = Session::new().unwrap();
+    sess.set_stream(a).unwrap();
+    assert!(!sess.is_blocking());
+
+    let mut sess = Session::new().unwrap();
+    sess.set_stream(crate::socket()).unwrap();
+    sess.handshake().await.unwrap();
+    assert!(sess.banner().unwrap().starts_with("SSH-2.0-"));
 }
 
 /*
@@ -142,6 +541,15 @@ async fn keepalive() {
     sess.keepalive_send().await.unwrap();
 }
 
+#[tokio::test]
+async fn disconnect() {
+    let sess = crate::authed_session().await;
+    sess.disconnect(Some(DisconnectCode::ByApplication), "bye", None)
+        .await
+        .unwrap();
+    assert!(sess.channel_session().await.is_err());
+}
+
 #[tokio::test]
 async fn scp_recv() {
     let sess = crate::authed_session().await;
@@ -161,6 +569,32 @@ async fn scp_recv() {
     assert!(data == expected);
 }
 
+#[tokio::test]
+async fn scp_upload() {
+    let td = tempdir().unwrap();
+    let local = td.path().join("local");
+    let remote = td.path().join("remote");
+    let data: Vec<u8> = (0..1_000_000u32).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&local, &data).unwrap();
+    let sess = crate::authed_session().await;
+
+    let sent = sess.scp_upload(&local, &remote, 0o600).await.unwrap();
+    assert_eq!(sent, data.len() as u64);
+    assert_eq!(std::fs::read(&remote).unwrap(), data);
+
+    // Vectored writes are gathered into whole packets.
+    let mut ch = sess.scp_send(&remote, 0o644, 9, None).await.unwrap();
+    let mut buf = BufExt::chain(BufExt::chain(&b"foo"[..], &b"bar"[..]), &b"baz"[..]);
+    while buf.has_remaining() {
+        ch.write_buf(&mut buf).await.unwrap();
+    }
+    ch.send_eof().await.unwrap();
+    ch.wait_eof().await.unwrap();
+    ch.close().await.unwrap();
+    ch.wait_close().await.unwrap();
+    assert_eq!(std::fs::read(&remote).unwrap(), b"foobarbaz");
+}
+
 #[tokio::test]
 async fn scp_send() {
     let td = tempdir().unwrap();
@@ -170,13 +604,13 @@ async fn scp_send() {
         .await
         .unwrap();
     ch.write_all(b"foobar").await.unwrap();
-    drop(ch);
-
-    // TODO why doesn't this work without sleep?
-    std::thread::sleep(std::time::Duration::from_millis(100));
+    ch.send_eof().await.unwrap();
+    ch.wait_eof().await.unwrap();
+    ch.close().await.unwrap();
+    ch.wait_close().await.unwrap();
 
     let mut actual = Vec::new();
-    File::open(&td.path().join("foo"))
+    File::open(td.path().join("foo"))
         .unwrap()
         .read_to_end(&mut actual)
         .unwrap();
@@ -1,11 +1,17 @@
+use async_ssh2::{BufferedReader, BufferedWriter, FileStat, OpenFlags, SortBy};
+use bytes::buf::{Buf, BufExt};
+use ssh2::OpenType;
 use std::{
     fs::{self, File},
-    io::prelude::*,
+    io::{prelude::*, SeekFrom},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, UNIX_EPOCH},
 };
 use tempfile::tempdir;
 use tokio::{
     self,
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    stream::StreamExt,
 };
 
 #[tokio::test]
@@ -17,15 +23,15 @@ async fn smoke() {
 #[tokio::test]
 async fn ops() {
     let td = tempdir().unwrap();
-    File::create(&td.path().join("foo")).unwrap();
-    fs::create_dir(&td.path().join("bar")).unwrap();
+    File::create(td.path().join("foo")).unwrap();
+    fs::create_dir(td.path().join("bar")).unwrap();
 
     let sess = crate::authed_session().await;
     let sftp = sess.sftp().await.unwrap();
     sftp.opendir(&td.path().join("bar")).await.unwrap();
     let mut foo = sftp.open(&td.path().join("foo")).await.unwrap();
     sftp.mkdir(&td.path().join("bar2"), 0o755).await.unwrap();
-    assert!(fs::metadata(&td.path().join("bar2"))
+    assert!(fs::metadata(td.path().join("bar2"))
         .map(|m| m.is_dir())
         .unwrap_or(false));
     sftp.rmdir(&td.path().join("bar2")).await.unwrap();
@@ -37,16 +43,13 @@ async fn ops() {
         .await
         .unwrap();
     let mut v = Vec::new();
-    File::open(&td.path().join("foo5"))
+    File::open(td.path().join("foo5"))
         .unwrap()
         .read_to_end(&mut v)
         .unwrap();
     assert_eq!(v, b"foo");
 
-    assert_eq!(
-        sftp.stat(&td.path().join("foo")).await.unwrap().size,
-        Some(0)
-    );
+    assert_eq!(sftp.stat(&td.path().join("foo")).await.unwrap().len(), 0);
     v.truncate(0);
     foo.read_to_end(&mut v).await.unwrap();
     assert_eq!(v, Vec::new());
@@ -61,8 +64,1303 @@ async fn ops() {
     let realpath = sftp.realpath(&td.path().join("foo2")).await.unwrap();
     assert_eq!(realpath, td.path().join("foo").canonicalize().unwrap());
 
-    let files = sftp.readdir(&td.path()).await.unwrap();
+    let files = sftp.readdir(td.path()).await.unwrap();
     assert_eq!(files.len(), 4);
 
     sftp.shutdown().await.unwrap();
 }
+
+#[tokio::test]
+async fn metadata() {
+    let td = tempdir().unwrap();
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    let file = td.path().join("file");
+    fs::write(&file, b"hello").unwrap();
+    let link = td.path().join("link");
+    sftp.symlink(&file, &link).await.unwrap();
+
+    let local = fs::metadata(&file).unwrap();
+    let meta = sftp.stat(&file).await.unwrap();
+    assert!(meta.is_file());
+    let timed = sftp
+        .stat_timeout(&file, Duration::from_secs(10))
+        .await
+        .unwrap();
+    assert_eq!(timed.len(), meta.len());
+    assert!(!meta.is_dir());
+    assert_eq!(meta.len(), 5);
+    assert_eq!(
+        meta.modified().unwrap(),
+        UNIX_EPOCH
+            + Duration::from_secs(
+                local
+                    .modified()
+                    .unwrap()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs()
+            )
+    );
+    assert!(meta.accessed().is_some());
+
+    assert!(sftp.stat(&link).await.unwrap().is_file());
+    assert!(sftp.lstat(&link).await.unwrap().is_symlink());
+    assert!(sftp.stat(td.path()).await.unwrap().is_dir());
+
+    let mut entries = sftp.readdir(td.path()).await.unwrap();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(entries[0].0, file);
+    assert_eq!(entries[0].1.len(), 5);
+    assert!(entries[1].1.is_symlink());
+}
+
+#[tokio::test]
+async fn statvfs() {
+    let td = tempdir().unwrap();
+    File::create(td.path().join("foo")).unwrap();
+
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+    let mut foo = sftp.open(&td.path().join("foo")).await.unwrap();
+    let stat = foo.statvfs().await.unwrap();
+    assert!(stat.bsize > 0);
+    assert!(stat.blocks >= stat.bfree);
+    assert!(stat.bfree >= stat.bavail);
+    foo.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn statvfs_path() {
+    let td = tempdir().unwrap();
+    File::create(td.path().join("foo")).unwrap();
+
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+    let dir = sftp.statvfs(td.path()).await.unwrap();
+    let file = sftp.statvfs(&td.path().join("foo")).await.unwrap();
+    assert!(dir.bsize > 0);
+    assert_eq!(dir.fsid, file.fsid);
+    match sftp.statvfs(&td.path().join("missing")).await {
+        Err(async_ssh2::Error::Path(path, _)) => assert_eq!(path, td.path().join("missing")),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Unlike opening a handle, statvfs needs no read permission.
+        let locked = td.path().join("locked");
+        fs::create_dir(&locked).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o100)).unwrap();
+        let res = sftp.statvfs(&locked).await;
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(res.unwrap().fsid, dir.fsid);
+    }
+}
+
+#[tokio::test]
+async fn seek() {
+    let td = tempdir().unwrap();
+    File::create(td.path().join("foo"))
+        .unwrap()
+        .write_all(b"0123456789")
+        .unwrap();
+
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+    let mut foo = sftp.open(&td.path().join("foo")).await.unwrap();
+    let mut buf = [0; 2];
+
+    assert_eq!(foo.seek(SeekFrom::Start(3)).await.unwrap(), 3);
+    foo.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"34");
+
+    assert_eq!(foo.seek(SeekFrom::Current(2)).await.unwrap(), 7);
+    foo.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"78");
+
+    assert_eq!(foo.seek(SeekFrom::End(-2)).await.unwrap(), 8);
+    for pos in [SeekFrom::End(-11), SeekFrom::Current(-9)] {
+        let err = foo.seek(pos).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+    foo.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"89");
+
+    foo.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn read_write_at() {
+    let td = tempdir().unwrap();
+    let path = td.path().join("foo");
+    fs::write(&path, b"0123456789").unwrap();
+
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+    let mut foo = sftp
+        .open_mode(
+            &path,
+            OpenFlags::READ | OpenFlags::WRITE,
+            0o644,
+            OpenType::File,
+        )
+        .await
+        .unwrap();
+    let mut buf = [0; 2];
+
+    foo.seek(SeekFrom::Start(8)).await.unwrap();
+    assert_eq!(foo.read_at(3, &mut buf).await.unwrap(), 2);
+    assert_eq!(&buf, b"34");
+    assert_eq!(foo.write_at(1, b"ab").await.unwrap(), 2);
+    assert_eq!(foo.read_at(0, &mut buf).await.unwrap(), 2);
+    assert_eq!(&buf, b"0a");
+    foo.close().await.unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"0ab3456789");
+}
+
+#[tokio::test]
+async fn write_all_at() {
+    let td = tempdir().unwrap();
+    let path = td.path().join("foo");
+    fs::write(&path, b"0123456789").unwrap();
+
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+    let mut foo = sftp
+        .open_mode(&path, OpenFlags::WRITE, 0o644, OpenType::File)
+        .await
+        .unwrap();
+    // Larger than a single sftp write, so libssh2 reports short writes.
+    let data = vec![b'x'; 1024 * 1024];
+    foo.write_all_at(5, &data).await.unwrap();
+    foo.write_all_at(1, b"ab").await.unwrap();
+    foo.close().await.unwrap();
+
+    let contents = fs::read(&path).unwrap();
+    assert_eq!(contents.len(), 5 + data.len());
+    assert_eq!(&contents[..5], b"0ab34");
+    assert!(contents[5..].iter().all(|&b| b == b'x'));
+}
+
+#[tokio::test]
+async fn buf_read() {
+    let td = tempdir().unwrap();
+    let path = td.path().join("lines");
+    fs::write(&path, b"foo\nbar\nbaz\n").unwrap();
+
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+    let mut file = sftp.open(&path).await.unwrap();
+    let mut line = String::new();
+    file.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "foo\n");
+    assert_eq!(file.seek(SeekFrom::Current(0)).await.unwrap(), 4);
+
+    let mut buf = [0; 4];
+    file.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"bar\n");
+    line.clear();
+    file.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "baz\n");
+    assert_eq!(file.bytes_transferred(), 12);
+    file.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn read_chunk_size() {
+    let td = tempdir().unwrap();
+    let path = td.path().join("data");
+    let data: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+    fs::write(&path, &data).unwrap();
+
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+    let mut file = sftp.open(&path).await.unwrap();
+    assert_eq!(file.read_chunk_size(), None);
+    file.set_read_chunk_size(4096);
+    assert_eq!(file.read_chunk_size(), Some(4096));
+
+    let mut read = Vec::new();
+    let mut buf = vec![0; 65536];
+    loop {
+        let n = file.read(&mut buf).await.unwrap();
+        assert!(n <= 4096);
+        if n == 0 {
+            break;
+        }
+        read.extend_from_slice(&buf[..n]);
+    }
+    assert_eq!(read, data);
+
+    file.set_read_chunk_size(0);
+    assert_eq!(file.read_chunk_size(), None);
+    file.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn drop_unclosed() {
+    let td = tempdir().unwrap();
+    fs::write(td.path().join("foo"), b"foo").unwrap();
+
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+    for _ in 0..10 {
+        let mut file = sftp.open(&td.path().join("foo")).await.unwrap();
+        let mut buf = [0; 1];
+        file.read_exact(&mut buf).await.unwrap();
+    }
+    assert_eq!(sftp.read(&td.path().join("foo")).await.unwrap(), b"foo");
+
+    // The file and then the sftp session are closed before the next request.
+    let file = sftp.open(&td.path().join("foo")).await.unwrap();
+    drop(file);
+    drop(sftp);
+    let sftp = sess.sftp().await.unwrap();
+    assert_eq!(sftp.read(&td.path().join("foo")).await.unwrap(), b"foo");
+    assert!(!sess.is_poisoned());
+}
+
+// Whether any process has `path` open, going by the fds in /proc.
+#[cfg(target_os = "linux")]
+fn is_open_anywhere(path: &Path) -> bool {
+    let path = path.canonicalize().unwrap();
+    fs::read_dir("/proc")
+        .unwrap()
+        .filter_map(|proc| fs::read_dir(proc.ok()?.path().join("fd")).ok())
+        .flatten()
+        .filter_map(|fd| fs::read_link(fd.ok()?.path()).ok())
+        .any(|target| target == path)
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn drop_during_request() {
+    let td = tempdir().unwrap();
+    let path = td.path().join("foo");
+    fs::write(&path, b"foo").unwrap();
+
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+    let file = sftp.open(&path).await.unwrap();
+    assert!(is_open_anywhere(&path));
+
+    // The file is dropped while the stat is waiting for its reply; closing
+    // it must wait for the stat to finish rather than cut into it.
+    let (stat, ()) = tokio::join!(sftp.stat(td.path()), async {
+        tokio::time::delay_for(Duration::from_millis(1)).await;
+        drop(file);
+    });
+    assert!(stat.unwrap().is_dir());
+    sftp.stat(&path).await.unwrap();
+    assert!(!is_open_anywhere(&path));
+    assert!(!sess.is_poisoned());
+}
+
+#[tokio::test]
+async fn shutdown() {
+    let td = tempdir().unwrap();
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    let data = vec![7u8; 100 * 1024];
+    let mut file = sftp.create(&td.path().join("foo")).await.unwrap();
+    tokio::io::copy(&mut &data[..], &mut file).await.unwrap();
+    assert_eq!(file.bytes_transferred(), data.len() as u64);
+    file.shutdown().await.unwrap();
+    file.shutdown().await.unwrap();
+    assert_eq!(fs::read(td.path().join("foo")).unwrap(), data);
+    file.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn flush() {
+    let td = tempdir().unwrap();
+    let path = td.path().join("foo");
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    let data = vec![3u8; 200 * 1024];
+    let mut file = sftp.create(&path).await.unwrap();
+    assert!(!file.sync_on_flush());
+    file.write_all(&data[..1000]).await.unwrap();
+    file.flush().await.unwrap();
+    let mut reopened = sftp.open(&path).await.unwrap();
+    let mut read = Vec::new();
+    reopened.read_to_end(&mut read).await.unwrap();
+    assert_eq!(read, &data[..1000]);
+
+    file.set_sync_on_flush(true);
+    file.write_all(&data[1000..]).await.unwrap();
+    file.flush().await.unwrap();
+    file.flush().await.unwrap();
+    let mut reopened = sftp.open(&path).await.unwrap();
+    read.clear();
+    reopened.read_to_end(&mut read).await.unwrap();
+    assert_eq!(read, data);
+    file.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn rate_limit() {
+    let td = tempdir().unwrap();
+    let path = td.path().join("foo");
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    let data = vec![5u8; 30_000];
+    let mut file = sftp.create(&path).await.unwrap();
+    file.set_rate_limit(Some(10_000));
+    assert_eq!(file.rate_limit(), Some(10_000));
+    let start = Instant::now();
+    file.write_all(&data).await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(1900));
+    file.close().await.unwrap();
+    assert_eq!(fs::read(&path).unwrap(), data);
+}
+
+#[tokio::test]
+async fn upload_dir_progress() {
+    let td = tempdir().unwrap();
+    let src = td.path().join("src");
+    fs::create_dir_all(&src).unwrap();
+    fs::write(src.join("small"), b"foo").unwrap();
+    fs::write(src.join("large"), vec![1u8; 200 * 1024]).unwrap();
+
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+    let dst = td.path().join("dst");
+    let (upload, progress) = sftp.upload_dir_progress(&src, &dst, false);
+    fn must_be_send<T: Send>(_: &T) {}
+    must_be_send(&upload);
+    must_be_send(&progress);
+    let (res, updates) = tokio::join!(upload, progress.collect::<Vec<_>>());
+    res.unwrap();
+
+    for (name, len) in &[("small", 3), ("large", 200 * 1024)] {
+        let last = updates
+            .iter()
+            .rev()
+            .find(|p| p.path == src.join(name))
+            .unwrap();
+        assert_eq!((last.done, last.total), (*len, *len));
+    }
+    assert_eq!(fs::read(dst.join("small")).unwrap(), b"foo");
+}
+
+#[tokio::test]
+async fn upload_dir() {
+    let td = tempdir().unwrap();
+    let src = td.path().join("src");
+    fs::create_dir_all(src.join("sub")).unwrap();
+    fs::write(src.join("foo"), b"foo").unwrap();
+    fs::write(src.join("sub").join("bar"), b"bar").unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+        fs::set_permissions(src.join("foo"), fs::Permissions::from_mode(0o600)).unwrap();
+        symlink(src.join("foo"), src.join("link")).unwrap();
+    }
+
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+    let dst = td.path().join("dst");
+    let mut calls = 0;
+    sftp.upload_dir(&src, &dst, false, |_, sent, total| {
+        assert!(sent <= total);
+        calls += 1;
+    })
+    .await
+    .unwrap();
+
+    assert!(calls >= 2);
+    assert_eq!(fs::read(dst.join("foo")).unwrap(), b"foo");
+    assert_eq!(fs::read(dst.join("sub").join("bar")).unwrap(), b"bar");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(dst.join("foo")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(fs::symlink_metadata(dst.join("link")).is_err());
+    }
+
+    let err = sftp
+        .upload_dir(&td.path().join("missing"), &dst, false, |_, _, _| {})
+        .await
+        .unwrap_err();
+    match err {
+        async_ssh2::Error::Path(ref path, _) => assert_eq!(path, &td.path().join("missing")),
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn upload_dir_symlink_cycle() {
+    let td = tempdir().unwrap();
+    let src = td.path().join("src");
+    fs::create_dir_all(src.join("sub")).unwrap();
+    fs::write(src.join("sub").join("foo"), b"foo").unwrap();
+    std::os::unix::fs::symlink(&src, src.join("sub").join("loop")).unwrap();
+
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+    let dst = td.path().join("dst");
+    sftp.upload_dir(&src, &dst, true, |_, _, _| {})
+        .await
+        .unwrap();
+
+    assert_eq!(fs::read(dst.join("sub").join("foo")).unwrap(), b"foo");
+    assert!(!dst.join("sub").join("loop").exists());
+}
+
+#[tokio::test]
+async fn download_dir() {
+    let td = tempdir().unwrap();
+    let src = td.path().join("src");
+    fs::create_dir_all(src.join("sub")).unwrap();
+    fs::write(src.join("foo"), b"foo").unwrap();
+    fs::write(src.join("sub").join("bar"), b"bar").unwrap();
+
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+    let dst = td.path().join("dst");
+    let errors = sftp.download_dir(&src, &dst, false, true).await.unwrap();
+    assert!(errors.is_empty());
+    assert_eq!(fs::read(dst.join("foo")).unwrap(), b"foo");
+    assert_eq!(fs::read(dst.join("sub").join("bar")).unwrap(), b"bar");
+
+    fs::write(dst.join("foo"), b"local").unwrap();
+    sftp.download_dir(&src, &dst, false, true).await.unwrap();
+    assert_eq!(fs::read(dst.join("foo")).unwrap(), b"local");
+    sftp.download_dir(&src, &dst, true, true).await.unwrap();
+    assert_eq!(fs::read(dst.join("foo")).unwrap(), b"foo");
+
+    assert!(sftp
+        .download_dir(&td.path().join("missing"), &dst, true, false)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn download_many() {
+    let td = tempdir().unwrap();
+    let src = td.path().join("src");
+    let dst = td.path().join("dst");
+    fs::create_dir_all(&src).unwrap();
+    fs::create_dir_all(&dst).unwrap();
+    let data: Vec<u8> = (0..100 * 1024).map(|i| (i % 251) as u8).collect();
+    fs::write(src.join("big"), &data).unwrap();
+    for i in 0..5 {
+        fs::write(src.join(i.to_string()), i.to_string()).unwrap();
+    }
+
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+    let mut names: Vec<String> = (0..5).map(|i| i.to_string()).collect();
+    names.insert(2, "big".into());
+    names.insert(4, "missing".into());
+    let pairs = names.iter().map(|name| (src.join(name), dst.join(name)));
+    let results = sftp.download_many(pairs, 3).await;
+
+    assert_eq!(results.len(), names.len());
+    for (name, res) in names.iter().zip(&results) {
+        if name == "missing" {
+            assert!(res.is_err());
+        } else {
+            res.as_ref().unwrap();
+            assert_eq!(
+                fs::read(dst.join(name)).unwrap(),
+                fs::read(src.join(name)).unwrap()
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn buffered_reader() {
+    let td = tempdir().unwrap();
+    let data: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+    fs::write(td.path().join("foo"), &data).unwrap();
+    fs::write(td.path().join("lines"), b"foo\nbar\n").unwrap();
+
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    let foo = sftp.open(&td.path().join("foo")).await.unwrap();
+    let mut reader = BufferedReader::with_capacity(64 * 1024, foo);
+    let mut v = Vec::new();
+    reader.read_to_end(&mut v).await.unwrap();
+    assert!(v == data);
+    reader.into_inner().close().await.unwrap();
+
+    let lines = sftp.open(&td.path().join("lines")).await.unwrap();
+    let mut reader = BufferedReader::new(lines);
+    let mut line = String::new();
+    reader.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "foo\n");
+    line.clear();
+    reader.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "bar\n");
+}
+
+#[tokio::test]
+async fn buffered_writer() {
+    let td = tempdir().unwrap();
+    let path = td.path().join("log");
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    let file = sftp.create(&path).await.unwrap();
+    let mut writer = BufferedWriter::with_capacity(64 * 1024, file);
+    let mut expected = Vec::new();
+    for i in 0..1000 {
+        let line = format!("line {}\n", i);
+        writer.write_all(line.as_bytes()).await.unwrap();
+        expected.extend_from_slice(line.as_bytes());
+    }
+    assert!(expected.len() < 64 * 1024);
+    assert_eq!(writer.buffer(), &expected[..]);
+    assert!(fs::read(&path).unwrap().is_empty());
+    writer.flush().await.unwrap();
+    assert!(writer.buffer().is_empty());
+    assert_eq!(fs::read(&path).unwrap(), expected);
+
+    // writes larger than the buffer go straight through
+    let big = vec![b'x'; 100 * 1024];
+    writer.write_all(b"tail\n").await.unwrap();
+    writer.write_all(&big).await.unwrap();
+    expected.extend_from_slice(b"tail\n");
+    expected.extend_from_slice(&big);
+    writer.write_all(b"end\n").await.unwrap();
+    expected.extend_from_slice(b"end\n");
+    writer.close().await.unwrap();
+    assert_eq!(fs::read(&path).unwrap(), expected);
+}
+
+#[tokio::test]
+async fn read_write() {
+    let td = tempdir().unwrap();
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    let path = td.path().join("foo");
+    sftp.write(&path, b"foobar").await.unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"foobar");
+    assert_eq!(sftp.read(&path).await.unwrap(), b"foobar");
+    sftp.write(&path, b"baz").await.unwrap();
+    assert_eq!(sftp.read(&path).await.unwrap(), b"baz");
+    assert!(sftp.read(&td.path().join("missing")).await.is_err());
+}
+
+#[tokio::test]
+async fn write_buf() {
+    let td = tempdir().unwrap();
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    let path = td.path().join("foo");
+    let mut file = sftp.create(&path).await.unwrap();
+    let mut buf = BufExt::chain(&b"foo"[..], &b"bar"[..]);
+    while buf.has_remaining() {
+        file.write_buf(&mut buf).await.unwrap();
+    }
+    file.close().await.unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"foobar");
+}
+
+#[tokio::test]
+async fn upload_atomic() {
+    let td = tempdir().unwrap();
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    let path = td.path().join("foo");
+    sftp.upload_atomic(b"foo", &path).await.unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"foo");
+    sftp.upload_atomic(b"foobar", &path).await.unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"foobar");
+    assert_eq!(fs::read_dir(td.path()).unwrap().count(), 1);
+
+    let missing = td.path().join("missing").join("foo");
+    assert!(sftp.upload_atomic(b"foo", &missing).await.is_err());
+}
+
+#[tokio::test]
+async fn copy_to() {
+    let td = tempdir().unwrap();
+    let data: Vec<u8> = (0..100 * 1024).map(|i| (i % 251) as u8).collect();
+    fs::write(td.path().join("src"), &data).unwrap();
+
+    let src_sess = crate::authed_session().await;
+    let src_sftp = src_sess.sftp().await.unwrap();
+    let dst_sess = crate::authed_session().await;
+    let dst_sftp = dst_sess.sftp().await.unwrap();
+    let copied = src_sftp
+        .copy_to(&td.path().join("src"), &dst_sftp, &td.path().join("dst"))
+        .await
+        .unwrap();
+    assert_eq!(copied, data.len() as u64);
+    assert!(fs::read(td.path().join("dst")).unwrap() == data);
+
+    let missing = td.path().join("missing");
+    match src_sftp
+        .copy_to(&missing, &dst_sftp, &td.path().join("dst"))
+        .await
+    {
+        Err(async_ssh2::Error::Path(path, _)) => assert_eq!(path, missing),
+        res => panic!("expected a path error, got {:?}", res),
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn chmod_chown_utimes() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let td = tempdir().unwrap();
+    let path = td.path().join("foo");
+    fs::write(&path, b"foo").unwrap();
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    sftp.chmod(&path, 0o600).await.unwrap();
+    let meta = fs::metadata(&path).unwrap();
+    assert_eq!(meta.permissions().mode() & 0o7777, 0o600);
+
+    sftp.chown(&path, Some(meta.uid()), None).await.unwrap();
+    let meta = fs::metadata(&path).unwrap();
+    assert_eq!(meta.permissions().mode() & 0o7777, 0o600);
+
+    sftp.utimes(&path, None, Some(1_000_000_000)).await.unwrap();
+    let after = fs::metadata(&path).unwrap();
+    assert_eq!(after.mtime(), 1_000_000_000);
+    assert_eq!(after.atime(), meta.atime());
+    assert_eq!(after.gid(), meta.gid());
+}
+
+#[tokio::test]
+async fn exists() {
+    let td = tempdir().unwrap();
+    fs::write(td.path().join("foo"), b"foo").unwrap();
+    fs::create_dir(td.path().join("dir")).unwrap();
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    assert!(sftp.exists(&td.path().join("foo")).await.unwrap());
+    assert!(sftp.is_file(&td.path().join("foo")).await.unwrap());
+    assert!(!sftp.is_dir(&td.path().join("foo")).await.unwrap());
+    assert!(sftp.is_dir(&td.path().join("dir")).await.unwrap());
+    assert!(!sftp.is_file(&td.path().join("dir")).await.unwrap());
+    assert!(!sftp.exists(&td.path().join("missing")).await.unwrap());
+    assert!(!sftp
+        .exists(&td.path().join("missing").join("foo"))
+        .await
+        .unwrap());
+    assert!(!sftp.is_file(&td.path().join("missing")).await.unwrap());
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        use std::os::unix::fs::symlink;
+
+        symlink("missing", td.path().join("dangling")).unwrap();
+        assert!(!sftp.exists(&td.path().join("dangling")).await.unwrap());
+
+        // Without search permission on the directory the entry cannot be
+        // looked up, which libssh2 does not tell apart from a missing one.
+        let dir = td.path().join("dir");
+        fs::write(dir.join("foo"), b"foo").unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o600)).unwrap();
+        let res = sftp.exists(&dir.join("foo")).await;
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(res.is_ok());
+    }
+}
+
+#[tokio::test]
+async fn mkdir_all() {
+    let td = tempdir().unwrap();
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    let path = td.path().join("a").join("b").join("c");
+    sftp.mkdir_all(&path, 0o755).await.unwrap();
+    assert!(fs::metadata(&path).unwrap().is_dir());
+    sftp.mkdir_all(&path, 0o755).await.unwrap();
+
+    fs::write(td.path().join("file"), b"foo").unwrap();
+    let blocked = td.path().join("file").join("sub");
+    match sftp.mkdir_all(&blocked, 0o755).await {
+        Err(async_ssh2::Error::Path(path, _)) => assert_eq!(path, td.path().join("file")),
+        res => panic!("expected a path error, got {:?}", res),
+    }
+}
+
+#[tokio::test]
+async fn remove_dir_all() {
+    let td = tempdir().unwrap();
+    let root = td.path().join("root");
+    let outside = td.path().join("outside");
+    fs::create_dir_all(root.join("a").join("b")).unwrap();
+    fs::create_dir(&outside).unwrap();
+    fs::write(root.join("foo"), b"foo").unwrap();
+    fs::write(root.join("a").join("b").join("bar"), b"bar").unwrap();
+    fs::write(outside.join("keep"), b"keep").unwrap();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(&outside, root.join("a").join("link")).unwrap();
+
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+    sftp.remove_dir_all(&root).await.unwrap();
+    assert!(!root.exists());
+    assert!(outside.join("keep").exists());
+
+    match sftp.remove_dir_all(&root).await {
+        Err(async_ssh2::Error::Path(path, _)) => assert_eq!(path, root),
+        res => panic!("expected a path error, got {:?}", res),
+    }
+}
+
+#[tokio::test]
+async fn readdir_terminates() {
+    let td = tempdir().unwrap();
+    for name in &["a", "b", "c"] {
+        File::create(td.path().join(name)).unwrap();
+    }
+    fs::create_dir(td.path().join("empty")).unwrap();
+
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+    let mut names: Vec<_> = sftp
+        .readdir(td.path())
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(path, _)| path.file_name().unwrap().to_owned())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["a", "b", "c", "empty"]);
+    assert!(sftp
+        .readdir(&td.path().join("empty"))
+        .await
+        .unwrap()
+        .is_empty());
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let locked = td.path().join("locked");
+        fs::create_dir(&locked).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        // Permissions are not enforced for privileged users.
+        if fs::read_dir(&locked).is_err() {
+            match sftp.readdir(&locked).await {
+                Err(async_ssh2::Error::SSH2(e)) => assert_ne!(e.code(), -16),
+                res => panic!("unexpected result: {:?}", res.map(|v| v.len())),
+            }
+        }
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn glob() {
+    let td = tempdir().unwrap();
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    let root = td.path();
+    fs::create_dir_all(root.join("logs/old/older")).unwrap();
+    for name in &[
+        "logs/a.gz",
+        "logs/b.gz",
+        "logs/c.log",
+        "logs/old/d.gz",
+        "logs/old/older/e.gz",
+    ] {
+        fs::write(root.join(name), b"").unwrap();
+    }
+    let pattern = |p: &str| format!("{}/{}", root.display(), p);
+
+    assert_eq!(
+        sftp.glob(&pattern("logs/*.gz")).await.unwrap(),
+        vec![root.join("logs/a.gz"), root.join("logs/b.gz")]
+    );
+    assert_eq!(
+        sftp.glob(&pattern("logs/[!a]*")).await.unwrap(),
+        vec![
+            root.join("logs/b.gz"),
+            root.join("logs/c.log"),
+            root.join("logs/old"),
+        ]
+    );
+    assert_eq!(
+        sftp.glob(&pattern("*/o?d/*.gz")).await.unwrap(),
+        vec![root.join("logs/old/d.gz")]
+    );
+    assert_eq!(
+        sftp.glob(&pattern("logs/**/*.gz")).await.unwrap(),
+        vec![
+            root.join("logs/a.gz"),
+            root.join("logs/b.gz"),
+            root.join("logs/old/d.gz"),
+            root.join("logs/old/older/e.gz"),
+        ]
+    );
+    assert_eq!(
+        sftp.glob(&pattern("logs/c.log")).await.unwrap(),
+        vec![root.join("logs/c.log")]
+    );
+    assert!(sftp.glob(&pattern("logs/*.txt")).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn read_dir_stream() {
+    let td = tempdir().unwrap();
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    for i in 0..100 {
+        fs::write(td.path().join(format!("file{}", i)), b"").unwrap();
+    }
+    let mut dir = sftp.read_dir_stream(td.path()).await.unwrap();
+    let mut names = Vec::new();
+    while let Some((path, stat)) = dir.next_entry().await.unwrap() {
+        assert!(stat.is_file());
+        names.push(path);
+    }
+    assert!(dir.next_entry().await.unwrap().is_none());
+    names.sort();
+    let mut expected: Vec<_> = (0..100)
+        .map(|i| td.path().join(format!("file{}", i)))
+        .collect();
+    expected.sort();
+    assert_eq!(names, expected);
+
+    let dir = sftp.read_dir_stream(td.path()).await.unwrap();
+    let mut names: Vec<_> = dir.map(|entry| entry.unwrap().0).collect().await;
+    names.sort();
+    assert_eq!(names, expected);
+}
+
+#[tokio::test]
+async fn read_to_string() {
+    let td = tempdir().unwrap();
+    let config = td.path().join("config.toml");
+    let binary = td.path().join("binary");
+    fs::write(&config, "name = \"ünïcode\"\n").unwrap();
+    fs::write(&binary, b"\xff\xfe").unwrap();
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    assert_eq!(
+        sftp.read_to_string(&config).await.unwrap(),
+        "name = \"ünïcode\"\n"
+    );
+    match sftp.read_to_string(&binary).await {
+        Err(async_ssh2::Error::Path(path, e)) => {
+            assert_eq!(path, binary);
+            match *e {
+                async_ssh2::Error::Utf8(e) => assert_eq!(e.into_bytes(), b"\xff\xfe"),
+                e => panic!("unexpected error: {:?}", e),
+            }
+        }
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[tokio::test]
+async fn truncate() {
+    let td = tempdir().unwrap();
+    let path = td.path().join("log");
+    fs::write(&path, b"hello world").unwrap();
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    sftp.truncate(&path, 5).await.unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"hello");
+    sftp.truncate(&path, 0).await.unwrap();
+    assert!(fs::read(&path).unwrap().is_empty());
+
+    let mut file = sftp
+        .open_mode(
+            &path,
+            OpenFlags::READ | OpenFlags::WRITE,
+            0o644,
+            OpenType::File,
+        )
+        .await
+        .unwrap();
+    file.write_all(b"abcdef").await.unwrap();
+    file.set_len(3).await.unwrap();
+    assert_eq!(file.stat().await.unwrap().len(), 3);
+    file.set_len(4).await.unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"abc\0");
+    file.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn readdir_filtered() {
+    let td = tempdir().unwrap();
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    fs::write(td.path().join("a.log"), b"").unwrap();
+    fs::write(td.path().join("b.txt"), b"").unwrap();
+    fs::create_dir(td.path().join("c.log")).unwrap();
+    let mut seen = 0;
+    let entries = sftp
+        .readdir_filtered(td.path(), |path, stat| {
+            seen += 1;
+            stat.is_file() && path.extension().is_some_and(|ext| ext == "log")
+        })
+        .await
+        .unwrap();
+    assert_eq!(seen, 3);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].0, td.path().join("a.log"));
+}
+
+#[tokio::test]
+async fn readdir_sorted() {
+    let td = tempdir().unwrap();
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    for (name, len, mtime) in &[("a", 30, 200), ("b", 10, 300), ("c", 20, 100)] {
+        let path = td.path().join(name);
+        fs::write(&path, vec![0; *len]).unwrap();
+        sftp.utimes(&path, None, Some(*mtime)).await.unwrap();
+    }
+    let names = |entries: Vec<(PathBuf, async_ssh2::Metadata)>| {
+        entries
+            .iter()
+            .map(|(path, _)| path.file_name().unwrap().to_str().unwrap().to_owned())
+            .collect::<Vec<_>>()
+    };
+    let by = |sort| sftp.readdir_sorted(td.path(), sort);
+    assert_eq!(names(by(SortBy::Name).await.unwrap()), ["a", "b", "c"]);
+    assert_eq!(names(by(SortBy::Size).await.unwrap()), ["b", "c", "a"]);
+    assert_eq!(names(by(SortBy::Modified).await.unwrap()), ["c", "a", "b"]);
+}
+
+#[test]
+fn long_format() {
+    let stat = |perm| {
+        async_ssh2::Metadata::from(FileStat {
+            size: Some(4096),
+            uid: Some(1000),
+            gid: Some(100),
+            perm,
+            atime: None,
+            mtime: Some(1_580_472_000),
+        })
+    };
+    assert_eq!(stat(Some(0o100644)).mode_string(), "-rw-r--r--");
+    assert_eq!(stat(Some(0o041777)).mode_string(), "drwxrwxrwt");
+    assert_eq!(stat(Some(0o120777)).mode_string(), "lrwxrwxrwx");
+    assert_eq!(stat(Some(0o106755)).mode_string(), "-rwsr-sr-x");
+    assert_eq!(stat(Some(0o107644)).mode_string(), "-rwSr-Sr-T");
+    assert_eq!(stat(None).mode_string(), "??????????");
+    assert_eq!(
+        stat(Some(0o100644)).long_format(Path::new("notes.txt")),
+        "-rw-r--r--  1000   100       4096 2020-01-31 12:00 notes.txt"
+    );
+    assert_eq!(
+        async_ssh2::Metadata::from(FileStat {
+            size: None,
+            uid: None,
+            gid: None,
+            perm: None,
+            atime: None,
+            mtime: None,
+        })
+        .long_format(Path::new("x")),
+        "??????????     ?     ?          ?                ? x"
+    );
+}
+
+#[tokio::test]
+async fn hardlink() {
+    let td = tempdir().unwrap();
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    let src = td.path().join("src");
+    let dst = td.path().join("dst");
+    fs::write(&src, b"shared").unwrap();
+    sftp.hardlink(&src, &dst).await.unwrap();
+    fs::write(&src, b"changed").unwrap();
+    assert_eq!(fs::read(&dst).unwrap(), b"changed");
+
+    match sftp
+        .hardlink(&td.path().join("missing"), &td.path().join("x"))
+        .await
+    {
+        Err(async_ssh2::Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::NotFound),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    // The sftp session itself is still usable.
+    assert!(sftp.stat(&dst).await.unwrap().is_file());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn lreadlink_stat() {
+    use std::os::unix::fs::symlink;
+
+    let td = tempdir().unwrap();
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    fs::write(td.path().join("file"), b"12345").unwrap();
+    symlink("file", td.path().join("link")).unwrap();
+    symlink("missing", td.path().join("dangling")).unwrap();
+
+    let (target, stat) = sftp.lreadlink_stat(&td.path().join("link")).await.unwrap();
+    assert_eq!(target, Path::new("file"));
+    let stat = stat.unwrap();
+    assert!(stat.is_file());
+    assert_eq!(stat.len(), 5);
+
+    let (target, stat) = sftp
+        .lreadlink_stat(&td.path().join("dangling"))
+        .await
+        .unwrap();
+    assert_eq!(target, Path::new("missing"));
+    assert!(stat.is_none());
+
+    assert!(sftp.lreadlink_stat(&td.path().join("file")).await.is_err());
+}
+
+#[tokio::test]
+async fn read_range() {
+    let td = tempdir().unwrap();
+    let path = td.path().join("data");
+    let data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(&path, &data).unwrap();
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    let mut file = sftp.open(&path).await.unwrap();
+    // Small reads make the range take many polls, most of them pending.
+    file.set_read_chunk_size(7);
+    file.seek(SeekFrom::Start(100)).await.unwrap();
+    let mut range = Vec::new();
+    (&mut file).take(100).read_to_end(&mut range).await.unwrap();
+    assert_eq!(range, &data[100..200]);
+
+    let mut next = [0; 10];
+    file.read_exact(&mut next).await.unwrap();
+    assert_eq!(next, &data[200..210]);
+
+    file.seek(SeekFrom::Start(990)).await.unwrap();
+    let mut tail = Vec::new();
+    (&mut file).take(100).read_to_end(&mut tail).await.unwrap();
+    assert_eq!(tail, &data[990..]);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn copy() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let td = tempdir().unwrap();
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    let src = td.path().join("src");
+    let dst = td.path().join("dst");
+    let data: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+    fs::write(&src, &data).unwrap();
+    fs::set_permissions(&src, fs::Permissions::from_mode(0o640)).unwrap();
+    fs::write(&dst, b"old contents that are longer than nothing").unwrap();
+
+    // Which method is used depends on the OpenSSH version under test.
+    sftp.copy(&src, &dst).await.unwrap();
+    assert_eq!(fs::read(&dst).unwrap(), data);
+    let new = td.path().join("new");
+    sftp.copy(&src, &new).await.unwrap();
+    assert_eq!(
+        fs::metadata(&new).unwrap().permissions().mode() & 0o777,
+        0o640
+    );
+
+    match sftp.copy(&td.path().join("missing"), &dst).await {
+        Err(async_ssh2::Error::Path(path, _)) => assert_eq!(path, td.path().join("missing")),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    // Copying a file onto itself must not truncate it.
+    match sftp.copy(&src, &td.path().join(".").join("src")).await {
+        Err(async_ssh2::Error::Path(_, e)) => match *e {
+            async_ssh2::Error::Io(e) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput),
+            e => panic!("unexpected error: {:?}", e),
+        },
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(fs::read(&src).unwrap(), data);
+}
+
+#[tokio::test]
+async fn sync_dir() {
+    let td = tempdir().unwrap();
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    fs::write(td.path().join("tmp"), b"data").unwrap();
+    sftp.rename(&td.path().join("tmp"), &td.path().join("file"), None)
+        .await
+        .unwrap();
+    sftp.sync_dir(td.path()).await.unwrap();
+
+    let missing = td.path().join("missing");
+    match sftp.sync_dir(&missing).await {
+        Err(async_ssh2::Error::Path(path, _)) => assert_eq!(path, missing),
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[tokio::test]
+async fn read_eof() {
+    let td = tempdir().unwrap();
+    let path = td.path().join("small");
+    fs::write(&path, b"hello").unwrap();
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    let mut file = sftp.open(&path).await.unwrap();
+    assert_eq!(file.read(&mut []).await.unwrap(), 0);
+    let mut buf = [0; 64];
+    let mut data = Vec::new();
+    loop {
+        let n = file.read(&mut buf).await.unwrap();
+        if n == 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..n]);
+    }
+    assert_eq!(data, b"hello");
+    // EOF is sticky, and an empty read at EOF does not fail either.
+    assert_eq!(file.read(&mut buf).await.unwrap(), 0);
+    assert_eq!(file.read(&mut []).await.unwrap(), 0);
+
+    let mut file = sftp.open(&path).await.unwrap();
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).await.unwrap();
+    assert_eq!(data, b"hello");
+    fs::write(td.path().join("empty"), b"").unwrap();
+    let mut file = sftp.open(&td.path().join("empty")).await.unwrap();
+    assert_eq!(file.read(&mut buf).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn append() {
+    let td = tempdir().unwrap();
+    let path = td.path().join("log");
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    let mut file = sftp.append(&path).await.unwrap();
+    file.write_all(b"first\n").await.unwrap();
+    file.close().await.unwrap();
+    let mut file = sftp.append(&path).await.unwrap();
+    file.write_all(b"second\n").await.unwrap();
+    file.close().await.unwrap();
+
+    assert_eq!(fs::read(&path).unwrap(), b"first\nsecond\n");
+}
+
+#[tokio::test]
+async fn upload_verified() {
+    let td = tempdir().unwrap();
+    let local = td.path().join("local");
+    let remote = td.path().join("remote");
+    let data: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+    fs::write(&local, &data).unwrap();
+
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+    let len = sftp.upload_verified(&local, &remote).await.unwrap();
+    assert_eq!(len, data.len() as u64);
+    assert_eq!(fs::read(&remote).unwrap(), data);
+
+    match sftp
+        .upload_verified(&td.path().join("missing"), &remote)
+        .await
+    {
+        Err(async_ssh2::Error::Path(path, _)) => assert_eq!(path, td.path().join("missing")),
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[tokio::test]
+async fn rename_modes() {
+    let td = tempdir().unwrap();
+    let (a, b, c) = (
+        td.path().join("a"),
+        td.path().join("b"),
+        td.path().join("c"),
+    );
+    fs::write(&a, b"a").unwrap();
+    fs::write(&b, b"b").unwrap();
+
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    match sftp.rename_noclobber(&a, &b).await {
+        Err(async_ssh2::Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::AlreadyExists),
+        res => panic!("unexpected result: {:?}", res),
+    }
+    assert_eq!(fs::read(&b).unwrap(), b"b");
+    sftp.rename_noclobber(&a, &c).await.unwrap();
+    assert!(!a.exists());
+
+    sftp.rename_overwrite(&c, &b).await.unwrap();
+    assert!(!c.exists());
+    assert_eq!(fs::read(&b).unwrap(), b"a");
+
+    // A missing source must not cost the destination.
+    assert!(sftp.rename_overwrite(&c, &b).await.is_err());
+    assert_eq!(fs::read(&b).unwrap(), b"a");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn resolve() {
+    use std::os::unix::fs::symlink;
+
+    let td = tempdir().unwrap();
+    let dir = td.path();
+    fs::write(dir.join("file"), b"foo").unwrap();
+    symlink("file", dir.join("one")).unwrap();
+    symlink(dir.join("one"), dir.join("two")).unwrap();
+    symlink("loop_b", dir.join("loop_a")).unwrap();
+    symlink("loop_a", dir.join("loop_b")).unwrap();
+    symlink("missing", dir.join("dangling")).unwrap();
+
+    let sess = crate::authed_session().await;
+    let sftp = sess.sftp().await.unwrap();
+
+    let (target, stat) = sftp.resolve(&dir.join("two")).await.unwrap();
+    assert_eq!(target, dir.join("file"));
+    assert!(stat.is_file());
+    assert_eq!(stat.len(), 3);
+
+    let (target, _) = sftp.resolve(&dir.join("file")).await.unwrap();
+    assert_eq!(target, dir.join("file"));
+
+    match sftp.resolve(&dir.join("loop_a")).await {
+        Err(async_ssh2::Error::Path(path, _)) => assert_eq!(path, dir.join("loop_a")),
+        res => panic!("unexpected result: {:?}", res.map(|(path, _)| path)),
+    }
+    match sftp.resolve(&dir.join("dangling")).await {
+        Err(async_ssh2::Error::Path(path, _)) => assert_eq!(path, dir.join("missing")),
+        res => panic!("unexpected result: {:?}", res.map(|(path, _)| path)),
+    }
+}
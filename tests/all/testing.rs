@@ -0,0 +1,49 @@
+use async_ssh2::testing::TestServer;
+use std::fs;
+
+#[tokio::test]
+async fn smoke() {
+    let server = TestServer::start().await.unwrap();
+    let sess = server.session().await.unwrap();
+    assert!(sess.authenticated());
+    assert!(server.dir().join("sshd_config").exists());
+    let dir = server.dir().to_owned();
+    drop(sess);
+    drop(server);
+    assert!(!dir.exists());
+}
+
+#[tokio::test]
+async fn readdir_upload_download() {
+    let server = TestServer::start().await.unwrap();
+    let sess = server.session().await.unwrap();
+    let sftp = sess.sftp().await.unwrap();
+
+    let local = server.dir().join("local");
+    fs::create_dir_all(local.join("sub")).unwrap();
+    fs::write(local.join("a"), b"foo").unwrap();
+    fs::write(local.join("sub/b"), b"bar").unwrap();
+
+    let remote = server.dir().join("remote");
+    sftp.upload_dir(&local, &remote, false, |_, _, _| {})
+        .await
+        .unwrap();
+    let mut names: Vec<_> = sftp
+        .readdir(&remote)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(path, _)| path.file_name().unwrap().to_owned())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["a", "sub"]);
+
+    let back = server.dir().join("back");
+    let errors = sftp
+        .download_dir(&remote, &back, false, true)
+        .await
+        .unwrap();
+    assert!(errors.is_empty());
+    assert_eq!(fs::read(back.join("a")).unwrap(), b"foo");
+    assert_eq!(fs::read(back.join("sub/b")).unwrap(), b"bar");
+}
@@ -58,3 +58,5 @@ sleep 2
 # Run the tests against it
 cargo test --all -- --nocapture
 cargo test --features vendored-openssl -- --nocapture
+cargo test --features testing testing:: -- --nocapture
+cargo test --features raw-access session::raw_access -- --nocapture